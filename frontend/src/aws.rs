@@ -13,8 +13,10 @@ use dioxus::prelude::*;
 use gloo_worker::{Spawnable, WorkerBridge};
 
 use model::events::{Event, *};
-use worker::AwsCredsEncrypted;
+
+use crate::errors::{check_hello, BACKEND_ERROR};
 use worker::{AwsCreds, BackendWorker, WorkerRequest, WorkerResponse, WorkerStatus};
+use worker::{AwsCredsEncrypted, DEFAULT_AWS_REGION, DEFAULT_AWS_SERVICE};
 
 // These are here temporarily to simplify debugging (the values will already be in the form)
 // TODO: remove values
@@ -47,6 +49,8 @@ impl Default for LoginSettings {
                 lambda_host: String::from(FUNCTION_HOST),
                 key_id: String::from(AWS_KEY_ID),
                 access_key: String::new(),
+                region: String::from(DEFAULT_AWS_REGION),
+                service: String::from(DEFAULT_AWS_SERVICE),
             },
             password: String::new(),
             show_settings: true,
@@ -64,6 +68,7 @@ pub struct WorkerStatusHandle {
 
 pub fn init_worker_state_actor(cx: &Scope) {
     let worker_state = use_atom_ref(&cx, WORKER_STATUS).clone();
+    let error = use_atom_ref(&cx, BACKEND_ERROR).clone();
     let actor = use_coroutine(&cx, |mut rx: UnboundedReceiver<WorkerStatus>| async move {
         while let Some(status) = rx.next().await {
             if let WorkerStatus::CredsEncrypted(creds) = &status {
@@ -82,10 +87,10 @@ pub fn init_worker_state_actor(cx: &Scope) {
     let b_actor = actor.clone();
     let bridge = bridge.borrow().fork(Some(move |response| {
         warn!("worker bridge got response {:?}", response);
-        if let WorkerResponse::Status(status) = response {
-            b_actor.send(status)
-        } else {
-            warn!("unexpected response from worker: {:?}", response);
+        match response {
+            WorkerResponse::Status(status) => b_actor.send(status),
+            WorkerResponse::Hello(hello) => check_hello(hello, &error),
+            _ => warn!("unexpected response from worker: {:?}", response),
         }
     }));
 
@@ -140,12 +145,32 @@ pub fn AwsSettingsForm(cx: Scope) -> Element {
             }
         }
         br {},
+        input {
+            placeholder: "AWS region",
+            value: "{prev.creds.region}",
+            oninput: move |ev|  {
+                login_settings.with_mut(|prev| {
+                    prev.creds.region = ev.value.clone();
+                });
+            }
+        }
+        br {},
+        input {
+            placeholder: "AWS service",
+            value: "{prev.creds.service}",
+            oninput: move |ev|  {
+                login_settings.with_mut(|prev| {
+                    prev.creds.service = ev.value.clone();
+                });
+            }
+        }
+        br {},
         button {
             onclick: move |ev|  {
                 //login_settings.with_mut(|prev| {
                     let password  = login_settings.password.clone();
                     let creds  = login_settings.creds.clone();
-                    worker_handle.read().bridge.send(WorkerRequest::SetCredsPlaintext(password, creds.lambda_host, creds.key_id, creds.access_key));
+                    worker_handle.read().bridge.send(WorkerRequest::SetCredsPlaintext(password, creds.lambda_host, creds.key_id, creds.access_key, creds.region, creds.service));
                     //prev.creds.access_key = ev.value.clone();
                 //});
             },
@@ -176,7 +201,9 @@ pub fn AwsSettingsForm(cx: Scope) -> Element {
                                password,
                                creds.lambda_host,
                                creds.key_id,
-                               creds.access_key
+                               creds.access_key,
+                               creds.region,
+                               creds.service
                                )
                            );
                     }
@@ -13,7 +13,8 @@ use model::entry::*;
 use model::events::*;
 use model::feed::FeedID;
 
-use crate::feeds::FEEDS;
+use crate::errors::{decode_response, BACKEND_ERROR};
+use crate::feeds::ask;
 use crate::WORKER_BRIDGE;
 use worker::{BackendWorker, WorkerRequest, WorkerResponse};
 
@@ -67,6 +68,12 @@ impl Ord for Entry {
 struct DisplayedEntries {
     unread: Vector<Entry>,
     read: Vector<Entry>,
+    // the cursor of the next page of unread entries, if there is one
+    next_cursor: Option<String>,
+    // the feed shown, entries of all the feeds are shown if it's not set
+    feed_id: Option<FeedID>,
+    // what the last refresh of the feeds has brought
+    refresh_summary: Option<String>,
 }
 
 impl Default for DisplayedEntries {
@@ -74,16 +81,21 @@ impl Default for DisplayedEntries {
         DisplayedEntries {
             unread: Vector::new(),
             read: Vector::new(),
+            next_cursor: None,
+            feed_id: None,
+            refresh_summary: None,
         }
     }
 }
 
 enum EntriesAction {
     Replace(Vec<StoredEntry>),
+    Append(Vec<StoredEntry>),
     MarkPendingRead(EntryID, u64),
-    MarkFinallyReadUnread(Vec<(EntryID, u64, i64)>),
+    MarkFinallyReadUnread(Vec<MarkedEntry>),
     MarkPendingUnread(EntryID, u64),
     BackendResponse(Option<Vec<u8>>),
+    Refreshed(Option<Vec<u8>>),
 }
 
 /// The entries processor coroutine, handles all the signals related to feed entries processing
@@ -91,6 +103,9 @@ enum EntriesAction {
 pub struct EntriesHandle {
     actor: CoroutineHandle<EntriesAction>,
     bridge: WorkerBridge<BackendWorker>,
+    // the responses to the refreshes come here, to fetch the entries after them
+    refresh_bridge: WorkerBridge<BackendWorker>,
+    entries: UseAtomRef<DisplayedEntries>,
 }
 
 impl EntriesHandle {
@@ -114,28 +129,121 @@ impl EntriesHandle {
         self.bridge.send(WorkerRequest::BackendRequest(data));
     }
 
-    pub fn handle_fetch_entries(&self) {
-        let event = Event::AllEntries(AllEntries {
-            unread_only: true,
-            feed_id: None,
-        });
+    /// marks all the entries given read with one request
+    pub fn handle_mark_visible_read(&self, visible: Vec<(EntryID, u64)>) {
+        if visible.is_empty() {
+            return;
+        }
+        for (entry_id, published) in visible.iter() {
+            self.actor
+                .send(EntriesAction::MarkPendingRead(entry_id.clone(), *published));
+        }
+        let event = Event::MarkReadUnread(
+            visible
+                .into_iter()
+                .map(|(entry_id, published)| (entry_id, published, true))
+                .collect(),
+        );
+        let data = serde_json::to_vec(&event).unwrap();
+        self.bridge.send(WorkerRequest::BackendRequest(data));
+    }
+
+    /// the entries are moved to the read ones when the backend confirms them
+    pub fn handle_mark_all_read(&self, request: MarkAllRead) {
+        let event = Event::MarkAllRead(request);
         let data = serde_json::to_vec(&event).unwrap();
         self.bridge.send(WorkerRequest::BackendRequest(data));
     }
 
+    pub fn handle_fetch_entries(&self) {
+        self.entries.write().feed_id = None;
+        self.fetch_entries_page(None);
+    }
+
+    pub fn handle_fetch_feed_entries(&self, feed_id: FeedID) {
+        self.entries.write().feed_id = Some(feed_id);
+        self.fetch_entries_page(None);
+    }
+
+    pub fn handle_fetch_more_entries(&self) {
+        // the cursor is taken away, so the page is requested only once while scrolling
+        let cursor = self.entries.write().next_cursor.take();
+        if cursor.is_some() {
+            self.fetch_entries_page(cursor);
+        }
+    }
+
+    /// loads the next page, when the list of entries is scrolled close to its end
+    pub fn handle_scroll(&self, container_id: &str) {
+        let container = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id(container_id));
+        if let Some(container) = container {
+            let left =
+                container.scroll_height() - container.scroll_top() - container.client_height();
+            if left < container.client_height() {
+                self.handle_fetch_more_entries();
+            }
+        }
+    }
+
+    /// a page may not fill the list, and then no scroll would load the next one,
+    /// so the list is checked once it is drawn
+    pub fn handle_page_shown(&self, container_id: &'static str) {
+        let handle = self.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            // the changes are applied to the page on the next animation frame,
+            // so the list is measured on the one after it
+            next_animation_frame().await;
+            next_animation_frame().await;
+            handle.handle_scroll(container_id);
+        });
+    }
+
+    fn fetch_entries_page(&self, cursor: Option<String>) {
+        let feed_id = self.entries.read().feed_id;
+        self.bridge.send(entries_page_request(feed_id, cursor));
+    }
+
+    /// the entries of all the feeds are fetched once the report of the refresh arrives
     pub fn handle_refresh(&self) {
+        {
+            let mut entries = self.entries.write();
+            entries.feed_id = None;
+            entries.refresh_summary = Some("refreshing feeds...".to_string());
+        }
         let event = Event::RefreshFeeds(RefreshFeeds { force: true });
         let data = serde_json::to_vec(&event).unwrap();
-        self.bridge.send(WorkerRequest::BackendRequest(data));
-        self.handle_fetch_entries();
+        self.refresh_bridge
+            .send(WorkerRequest::BackendRequest(data));
     }
 }
 
+async fn next_animation_frame() {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        if let Some(window) = web_sys::window() {
+            let _ = window.request_animation_frame(&resolve);
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+fn entries_page_request(feed_id: Option<FeedID>, cursor: Option<String>) -> WorkerRequest {
+    let event = Event::AllEntries(AllEntries {
+        unread_only: true,
+        feed_id,
+        cursor,
+        ..Default::default()
+    });
+    WorkerRequest::BackendRequest(serde_json::to_vec(&event).unwrap())
+}
+
 pub fn init_entries_actor(cx: &Scope) {
     let entries = use_atom_ref(&cx, ENTRIES).clone();
+    let error = use_atom_ref(&cx, BACKEND_ERROR).clone();
     let actor = use_coroutine(&cx, |mut rx: UnboundedReceiver<EntriesAction>| async move {
         while let Some(action) = rx.next().await {
-            handle_action(action, entries.clone());
+            handle_action(action, entries.clone(), error.clone());
         }
     })
     .to_owned();
@@ -156,9 +264,30 @@ pub fn init_entries_actor(cx: &Scope) {
         }
     }));
 
+    let r_actor = actor.clone();
+    let r_entries = use_atom_ref(&cx, ENTRIES).clone();
+    // its responses are the pages of entries, so they go where the other pages go
+    let fetcher = bridge.fork(Some({
+        let actor = actor.clone();
+        move |response| {
+            if let WorkerResponse::BackendResponse(data) = response {
+                actor.send(EntriesAction::BackendResponse(data))
+            }
+        }
+    }));
+    let refresh_bridge = bridge.fork(Some(move |response| {
+        if let WorkerResponse::BackendResponse(data) = response {
+            r_actor.send(EntriesAction::Refreshed(data));
+            let feed_id = r_entries.read().feed_id;
+            fetcher.send(entries_page_request(feed_id, None));
+        }
+    }));
+
     let handle = EntriesHandle {
         actor: actor.to_owned(),
         bridge,
+        refresh_bridge,
+        entries: use_atom_ref(&cx, ENTRIES).clone(),
     };
     use_context_provider(cx, move || handle);
 }
@@ -173,7 +302,11 @@ fn find_entry<'a>(
         .position(|e| &e.stored.entry_id == id && &e.stored.published == published)
 }
 
-fn handle_action(action: EntriesAction, atom_entries: UseAtomRef<DisplayedEntries>) {
+fn handle_action(
+    action: EntriesAction,
+    atom_entries: UseAtomRef<DisplayedEntries>,
+    atom_error: UseAtomRef<Option<ErrorResponse>>,
+) {
     // NOTDO: let mut entries = atom_entries.write();
     // we are recursively calling handle_action, so borrowing atom_entries for writing
     // will be double mutable borrowing
@@ -198,6 +331,21 @@ fn handle_action(action: EntriesAction, atom_entries: UseAtomRef<DisplayedEntrie
             }
         }
 
+        // the page is not necessarily older than the ones loaded before it,
+        // so its entries are merged into the list in order rather than added at its end
+        EntriesAction::Append(new_entries) => {
+            let mut entries = atom_entries.write();
+            for entry in new_entries {
+                if find_entry(&entries.unread, &entry.entry_id, &entry.published).is_none() {
+                    let unread = entry.read_ts == 0;
+                    entries.unread.insert_ord(Entry {
+                        stored: entry,
+                        unread,
+                    });
+                }
+            }
+        }
+
         EntriesAction::MarkPendingRead(id, published) => {
             let mut entries = atom_entries.write();
             if let Some(pos) = find_entry(&entries.unread, &id, &published) {
@@ -223,25 +371,62 @@ fn handle_action(action: EntriesAction, atom_entries: UseAtomRef<DisplayedEntrie
             }
         }
 
-        EntriesAction::MarkFinallyReadUnread(mut marked) => {
-            let mut entries = atom_entries.write();
+        EntriesAction::MarkFinallyReadUnread(marked) => {
+            let mut failed = Vec::new();
+            for MarkedEntry {
+                entry_id,
+                published,
+                read_ts,
+                error,
+            } in marked
+            {
+                // the entries failed go back where they were, the ones marked in bulk
+                // are not moved yet
+                let read = (read_ts != 0) != error.is_some();
+                let (in_unread, in_read) = {
+                    let entries = atom_entries.read();
+                    (
+                        find_entry(&entries.unread, &entry_id, &published).is_some(),
+                        find_entry(&entries.read, &entry_id, &published).is_some(),
+                    )
+                };
+                let action = match (read, in_unread, in_read) {
+                    (true, true, _) => {
+                        Some(EntriesAction::MarkPendingRead(entry_id.clone(), published))
+                    }
+                    (false, _, true) => Some(EntriesAction::MarkPendingUnread(
+                        entry_id.clone(),
+                        published,
+                    )),
+                    _ => None,
+                };
+                if let Some(action) = action {
+                    handle_action(action, atom_entries.clone(), atom_error.clone());
+                }
+                if let Some(error) = error {
+                    failed.push(error);
+                    continue;
+                }
 
-            while let Some((entry_id, published, read_ts)) = marked.pop() {
-                let list = if read_ts == 0 {
-                    // message was about entry marked unread
-                    &mut entries.unread
-                } else {
+                let mut entries = atom_entries.write();
+                let list = if read {
                     &mut entries.read
+                } else {
+                    &mut entries.unread
                 };
-                if let Some(pos) = list
-                    .iter()
-                    .position(|e| e.stored.entry_id == entry_id && e.stored.published == published)
-                {
+                if let Some(pos) = find_entry(list, &entry_id, &published) {
                     list.get_mut(pos).map(|mut e| e.stored.read_ts = read_ts);
                 } else {
-                    warn!("entry not found: {:?}", (entry_id, published, read_ts));
+                    // the bulk marks entries which were not loaded
+                    trace!("entry not found: {:?}", (entry_id, published, read_ts));
                 }
             }
+            if let Some(error) = failed.first() {
+                *atom_error.write() = Some(ErrorResponse::new(
+                    ErrorCode::StorageError,
+                    format!("{} entries could not be marked: {}", failed.len(), error),
+                ));
+            }
         }
 
         EntriesAction::MarkPendingUnread(entry_id, published) => {
@@ -259,29 +444,81 @@ fn handle_action(action: EntriesAction, atom_entries: UseAtomRef<DisplayedEntrie
         }
 
         EntriesAction::BackendResponse(data) => {
-            if let Some(data) = data {
-                warn!("worker response: {}", String::from_utf8_lossy(&data));
-                let response = serde_json::from_slice(&data).expect("cannot deserialize response");
-
+            if let Some(response) = decode_response(data, &atom_error) {
                 warn!("worker response deserialized: {:?}", response);
                 match response {
-                    Response::AllEntries(entries) => {
-                        handle_action(EntriesAction::Replace(entries), atom_entries.clone())
+                    Response::AllEntries(page) => {
+                        let EntriesPage {
+                            entries,
+                            next_cursor,
+                            cursor,
+                        } = page;
+                        atom_entries.write().next_cursor = next_cursor;
+                        // the first page replaces whatever was loaded before
+                        let action = if cursor.is_none() {
+                            EntriesAction::Replace(entries)
+                        } else {
+                            EntriesAction::Append(entries)
+                        };
+                        handle_action(action, atom_entries.clone(), atom_error.clone());
                     }
                     Response::MarkedRead(entries) => {
                         handle_action(
                             EntriesAction::MarkFinallyReadUnread(entries),
                             atom_entries.clone(),
+                            atom_error.clone(),
+                        );
+                    }
+                    // only the failed entries are listed, the loaded ones the request
+                    // is about are marked read along with them
+                    Response::MarkAllReadReport(report) => {
+                        let failed: HashSet<_> = report
+                            .failed
+                            .iter()
+                            .map(|entry| (entry.entry_id.clone(), entry.published))
+                            .collect();
+                        let mut entries: Vec<MarkedEntry> = atom_entries
+                            .read()
+                            .unread
+                            .iter()
+                            .map(|entry| &entry.stored)
+                            .filter(|entry| report.request.matches(entry))
+                            .filter(|entry| {
+                                !failed.contains(&(entry.entry_id.clone(), entry.published))
+                            })
+                            .map(|entry| MarkedEntry {
+                                entry_id: entry.entry_id.clone(),
+                                published: entry.published,
+                                read_ts: report.read_ts,
+                                error: None,
+                            })
+                            .collect();
+                        entries.extend(report.failed);
+                        handle_action(
+                            EntriesAction::MarkFinallyReadUnread(entries),
+                            atom_entries.clone(),
+                            atom_error.clone(),
                         );
                     }
                     _ => {
                         warn!("bad response from backend: {:?}", response);
                     }
                 }
-            } else {
-                warn!("empty response from worker");
             }
         }
+
+        EntriesAction::Refreshed(data) => {
+            // the error of the refresh, if any, is shown by the banner
+            let summary = match decode_response(data, &atom_error) {
+                Some(Response::RefreshReport(report)) => Some(report.summary()),
+                Some(response) => {
+                    warn!("bad response from backend: {:?}", response);
+                    None
+                }
+                None => None,
+            };
+            atom_entries.write().refresh_summary = summary;
+        }
     }
 
     let entries = atom_entries.read();
@@ -296,21 +533,19 @@ fn handle_action(action: EntriesAction, atom_entries: UseAtomRef<DisplayedEntrie
 #[allow(non_snake_case)]
 pub fn Entries(cx: Scope) -> Element {
     let entries = use_atom_ref(&cx, ENTRIES);
-    let feeds = use_atom_ref(&cx, FEEDS);
-    let mut enabled = HashSet::new();
-    let feeds = &feeds.read().feeds;
-    for feed in feeds.iter() {
-        if feed.enabled {
-            enabled.insert(feed.stored.feed_id);
-        }
-    }
     let entries_handle = use_context::<EntriesHandle>(&cx).unwrap().to_owned();
     let entries: &DisplayedEntries = &entries.read();
+    let visible: Vec<(EntryID, u64)> = entries
+        .unread
+        .iter()
+        .map(|entry| (entry.stored.entry_id.clone(), entry.stored.published))
+        .collect();
+    let feed_id = entries.feed_id;
+    if entries.next_cursor.is_some() {
+        entries_handle.read().handle_page_shown("entries");
+    }
 
-    let entry_nodes = entries.unread.iter().filter_map(|entry| {
-        if !enabled.contains(&entry.stored.feed_id) {
-            return None
-        }
+    let entry_nodes = entries.unread.iter().map(|entry| {
         let link = entry.stored.link.clone().unwrap_or_default();
         let title = entry.stored.title.clone().unwrap_or_default();
 
@@ -320,10 +555,40 @@ pub fn Entries(cx: Scope) -> Element {
         let published = entry.stored.published.clone();
         let read = entry.stored.read_ts != 0;
         let key = id.0.clone();
+        let attachments = entry.stored.attachments.iter().map(|attachment| {
+            let url = attachment.url.clone();
+            let name = attachment.title.clone().unwrap_or_else(|| {
+                url.rsplit('/').next().unwrap_or_default().to_string()
+            });
+            let details = attachment_details(attachment);
+            rsx!(
+                li {
+                    key: "{url}",
+                    a {
+                        target: "_blank",
+                        href: "{url}",
+                        rel: "noopener noreferrer",
+                        "{name}"
+                    }
+                    small { " {details}" }
+                }
+            )
+        });
+        let episode = entry
+            .stored
+            .podcast
+            .as_ref()
+            .and_then(|podcast| podcast.episode.clone())
+            .map(|episode| rsx!(small { " episode {episode}" }));
+        // the feed had no date for the entry, so it's sorted by the time it was first seen
+        let estimated = entry.stored.published_source.is_estimated().then(|| rsx!(
+            small {
+                title: "publish date is unknown, the time of first fetch is used",
+                " ~"
+            }
+        ));
 
-
-
-       Some(rsx!(
+       rsx!(
         p {
             key: "{key}",
             div {
@@ -333,9 +598,14 @@ pub fn Entries(cx: Scope) -> Element {
                     rel: "noopener noreferrer",
                     "{title}"
                 }
+                estimated
+                episode
                 div {
                     dangerous_inner_html: "{html_description}",
                 }
+                ul {
+                    attachments
+                }
                 button {
                     disabled: "{read}",
                     onclick: move |_| entries_handle.read().handle_mark_read_unread(id.clone(), published, true),
@@ -343,13 +613,49 @@ pub fn Entries(cx: Scope) -> Element {
                 }
             }
         }
-        ))
+        )
     });
     cx.render(rsx! {
-       entry_nodes
+        div {
+            button {
+                title: "mark read all the entries shown",
+                onclick: move |_| entries_handle.read().handle_mark_visible_read(visible.clone()),
+                "Read all shown"
+            }
+            button {
+                title: "mark read the older entries, including the ones not loaded yet",
+                onclick: move |_| {
+                    let days = ask("Mark read the entries older than days", "1")
+                        .and_then(|days| days.trim().parse::<u64>().ok());
+                    if let Some(days) = days {
+                        // in seconds, like the publish dates of the entries
+                        let now = (js_sys::Date::now() / 1000.0) as u64;
+                        entries_handle.read().handle_mark_all_read(MarkAllRead {
+                            feed_id,
+                            older_than: Some(now.saturating_sub(days * 24 * 60 * 60)),
+                        });
+                    }
+                },
+                "Catch up"
+            }
+        }
+        entry_nodes
     })
 }
 
+/// shows what the last refresh of the feeds has brought, e.g. "3 new in X, Y failed: timeout"
+#[allow(non_snake_case)]
+pub fn RefreshSummary(cx: Scope) -> Element {
+    let entries = use_atom_ref(&cx, ENTRIES);
+    let summary = entries.read().refresh_summary.clone()?;
+    cx.render(rsx!(
+        span {
+            class: "f6 mid-gray ml2",
+            "{summary}"
+        }
+    ))
+}
+
 #[allow(non_snake_case)]
 pub fn ReadEntries(cx: Scope) -> Element {
     let entries = use_atom_ref(&cx, ENTRIES);
@@ -390,6 +696,37 @@ pub fn ReadEntries(cx: Scope) -> Element {
     })
 }
 
+/// mime type, size and duration of the attachment, as much as is known
+fn attachment_details(attachment: &Attachment) -> String {
+    let mut details = Vec::new();
+    if let Some(mime_type) = &attachment.mime_type {
+        details.push(mime_type.clone());
+    }
+    if let Some(length) = attachment.length {
+        details.push(format_size(length));
+    }
+    if let Some(duration) = attachment.duration {
+        details.push(format!(
+            "{}:{:02}:{:02}",
+            duration / 3600,
+            duration / 60 % 60,
+            duration % 60
+        ));
+    }
+    details.join(", ")
+}
+
+fn format_size(size: u64) -> String {
+    let mut size = size as f64;
+    for unit in ["B", "KiB", "MiB"] {
+        if size < 1024.0 {
+            return format!("{:.0} {}", size, unit);
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} GiB", size)
+}
+
 fn parse_description<'a>(description: &'a str) -> String {
     use quick_xml::events::Event;
     use quick_xml::Reader;
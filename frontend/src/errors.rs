@@ -0,0 +1,87 @@
+use dioxus::fermi::*;
+use dioxus::prelude::*;
+use log::warn;
+
+use model::events::*;
+
+/// The last error reported by the backend, shown until dismissed or replaced by a newer one
+pub static BACKEND_ERROR: AtomRef<Option<ErrorResponse>> = |_| None;
+
+/// Decodes the worker's response, storing anything that went wrong in the error atom,
+/// so the callers only deal with the responses they have asked for
+pub fn decode_response(
+    data: Option<Vec<u8>>,
+    atom_error: &UseAtomRef<Option<ErrorResponse>>,
+) -> Option<Response> {
+    let error = match data {
+        None => ErrorResponse::new(ErrorCode::Internal, "backend request failed"),
+        Some(data) => match serde_json::from_slice(&data) {
+            Ok(Response::Error(error)) => error,
+            Ok(response) => return Some(response),
+            Err(e) => ErrorResponse::new(
+                ErrorCode::Internal,
+                format!("cannot deserialize backend response: {}", e),
+            ),
+        },
+    };
+    warn!("backend error: {}", error);
+    *atom_error.write() = Some(error);
+    None
+}
+
+/// Reports the backend which cannot be talked to, either because it's unreachable
+/// or because it speaks another version of the protocol
+pub fn check_hello(
+    hello: Result<HelloResponse, String>,
+    atom_error: &UseAtomRef<Option<ErrorResponse>>,
+) {
+    let error = match hello {
+        Ok(hello) if hello.is_compatible() => {
+            if let StorageStatus::Unavailable(reason) = hello.storage {
+                ErrorResponse::new(ErrorCode::StorageError, reason)
+            } else {
+                return;
+            }
+        }
+        Ok(hello) => ErrorResponse::new(
+            ErrorCode::ProtocolMismatch,
+            format!(
+                "backend {} speaks protocol version {}, but the frontend expects {}",
+                hello.backend_version, hello.protocol_version, PROTOCOL_VERSION
+            ),
+        ),
+        Err(e) => ErrorResponse::new(
+            ErrorCode::Internal,
+            format!("backend is unreachable: {}", e),
+        ),
+    };
+    warn!("backend handshake: {}", error);
+    *atom_error.write() = Some(error);
+}
+
+#[allow(non_snake_case)]
+pub fn ErrorBanner(cx: Scope) -> Element {
+    let atom_error = use_atom_ref(&cx, BACKEND_ERROR);
+    let error = atom_error.read().clone();
+
+    error.and_then(|error| {
+        let code = format!("{:?}", error.code);
+        let message = error.message;
+        let retry = error
+            .retry_after
+            .map(|seconds| format!(", try again in {} seconds", seconds))
+            .unwrap_or_default();
+        cx.render(rsx!(
+            div {
+                class: "fl w-100 pa2 mb2 dark-red bg-washed-red",
+                strong { "{code}: " }
+                "{message}{retry}"
+                button {
+                    class: "ml2",
+                    onclick: move |_| *atom_error.write() = None,
+                    "x"
+                }
+            }
+        ))
+    })
+}
@@ -10,16 +10,18 @@ use model::feed::*;
 
 use crate::aws::*;
 use crate::entries::*;
+use crate::errors::{decode_response, BACKEND_ERROR};
 
 use crate::WORKER_BRIDGE;
 use gloo_worker::WorkerBridge;
 use worker::{BackendWorker, WorkerRequest, WorkerResponse};
 
 pub static FEEDS: AtomRef<DisplayedFeeds> = |_| DisplayedFeeds::default();
+// the feeds linked from the page the user has tried to add, to pick one of
+pub static FEED_CANDIDATES: AtomRef<Vec<FeedCandidate>> = |_| Vec::new();
 #[derive(PartialEq, Eq, Clone)]
 pub struct DisplayedFeed {
     pub stored: StoredFeed,
-    pub enabled: bool,
 }
 
 pub struct DisplayedFeeds {
@@ -34,7 +36,7 @@ impl Default for DisplayedFeeds {
 
 enum FeedsAction {
     Replace(Vec<StoredFeed>),
-    FlipEnabled(FeedID),
+    Candidates(Vec<FeedCandidate>),
     BackendResponse(Option<Vec<u8>>),
 }
 
@@ -47,48 +49,83 @@ pub struct FeedsHandle {
 
 impl FeedsHandle {
     pub fn handle_fetch_feeds(&self) {
-        let event = Event::AllFeeds(());
+        self.bridge.send(all_feeds_request());
+    }
+
+    /// the feeds are fetched again once the backend confirms the feed is added
+    pub fn handle_add_feed(&self, url: String) {
+        let event = Event::AddFeed(url);
         let data = serde_json::to_vec(&event).unwrap();
         self.bridge.send(WorkerRequest::BackendRequest(data));
     }
 
-    pub fn handle_flip_enabled(&self, id: FeedID) {
+    /// as with adding, the feeds are fetched again once the change is confirmed
+    pub fn handle_update_feed(&self, update: UpdateFeed) {
+        let event = Event::UpdateFeed(update);
+        let data = serde_json::to_vec(&event).unwrap();
+        self.bridge.send(WorkerRequest::BackendRequest(data));
+    }
 
-        //
+    pub fn handle_remove_feed(&self, feed_id: FeedID) {
+        let event = Event::RemoveFeed(RemoveFeed {
+            feed_id,
+            remove_entries: true,
+        });
+        let data = serde_json::to_vec(&event).unwrap();
+        self.bridge.send(WorkerRequest::BackendRequest(data));
     }
 }
 
-fn handle_action(action: FeedsAction, atom_feeds: UseAtomRef<DisplayedFeeds>) {
+fn all_feeds_request() -> WorkerRequest {
+    let event = Event::AllFeeds(());
+    WorkerRequest::BackendRequest(serde_json::to_vec(&event).unwrap())
+}
+
+fn handle_action(
+    action: FeedsAction,
+    atom_feeds: UseAtomRef<DisplayedFeeds>,
+    atom_candidates: UseAtomRef<Vec<FeedCandidate>>,
+    atom_error: UseAtomRef<Option<ErrorResponse>>,
+    fetcher: &WorkerBridge<BackendWorker>,
+) {
     match action {
         FeedsAction::Replace(mut new_feeds) => {
             let mut feeds = atom_feeds.write();
             feeds.feeds.clear();
             while let Some(feed) = new_feeds.pop() {
-                feeds.feeds.push(DisplayedFeed {
-                    stored: feed,
-                    enabled: true,
-                });
+                feeds.feeds.push(DisplayedFeed { stored: feed });
             }
         }
-        FeedsAction::FlipEnabled(id) => {
-            let mut feeds = atom_feeds.write();
+        FeedsAction::Candidates(candidates) => {
+            *atom_candidates.write() = candidates;
         }
         FeedsAction::BackendResponse(data) => {
-            if let Some(data) = data {
-                warn!("worker feeds response: {}", String::from_utf8_lossy(&data));
-                let response = serde_json::from_slice(&data).expect("cannot deserialize response");
-
+            if let Some(response) = decode_response(data, &atom_error) {
                 warn!("worker feeds response deserialized: {:?}", response);
                 match response {
-                    Response::AllFeeds(feeds) => {
-                        handle_action(FeedsAction::Replace(feeds), atom_feeds.clone())
+                    Response::AllFeeds(feeds) => handle_action(
+                        FeedsAction::Replace(feeds),
+                        atom_feeds.clone(),
+                        atom_candidates.clone(),
+                        atom_error.clone(),
+                        fetcher,
+                    ),
+                    Response::FeedCandidates(candidates) => handle_action(
+                        FeedsAction::Candidates(candidates),
+                        atom_feeds.clone(),
+                        atom_candidates.clone(),
+                        atom_error.clone(),
+                        fetcher,
+                    ),
+                    // the feed is added, changed or removed, the list is fetched again to show it
+                    Response::Ok(_) => {
+                        atom_candidates.write().clear();
+                        fetcher.send(all_feeds_request());
                     }
                     _ => {
                         warn!("bad response from backend: {:?}", response);
                     }
                 }
-            } else {
-                warn!("empty response from worker");
             }
         }
     }
@@ -146,15 +183,32 @@ pub fn init_event_actor<E: BackendEvent, H: Handle<E, AtomT>, AtomT>(
 
 pub fn init_feeds_actor(cx: &Scope) {
     let feeds = use_atom_ref(&cx, FEEDS).clone();
-    let actor = use_coroutine(&cx, |mut rx: UnboundedReceiver<FeedsAction>| async move {
-        while let Some(action) = rx.next().await {
-            handle_action(action, feeds.clone());
+    let candidates = use_atom_ref(&cx, FEED_CANDIDATES).clone();
+    let error = use_atom_ref(&cx, BACKEND_ERROR).clone();
+    let bridge = use_read(cx, WORKER_BRIDGE);
+
+    // the actor sends requests of its own after the changes of the feeds,
+    // their responses come back to it along with the actions
+    let (fetched_tx, fetched_rx) = futures::channel::mpsc::unbounded();
+    let fetcher = bridge.borrow().fork(Some(move |response| {
+        if let WorkerResponse::BackendResponse(data) = response {
+            let _ = fetched_tx.unbounded_send(FeedsAction::BackendResponse(data));
+        }
+    }));
+    let actor = use_coroutine(&cx, |rx: UnboundedReceiver<FeedsAction>| async move {
+        let mut actions = futures::stream::select(rx, fetched_rx);
+        while let Some(action) = actions.next().await {
+            handle_action(
+                action,
+                feeds.clone(),
+                candidates.clone(),
+                error.clone(),
+                &fetcher,
+            );
         }
     })
     .to_owned();
 
-    let bridge = use_read(cx, WORKER_BRIDGE);
-
     let b_actor = actor.clone();
     let bridge = bridge.borrow().fork(Some(move |response| {
         // TODO move callback to a separate function
@@ -180,16 +234,40 @@ pub fn init_feeds_actor(cx: &Scope) {
 pub fn Feeds(cx: Scope) -> Element {
     let feeds = use_atom_ref(&cx, FEEDS);
     let feeds_handle = use_context::<FeedsHandle>(&cx).unwrap().to_owned();
+    let entries_handle = use_context::<EntriesHandle>(&cx).unwrap().to_owned();
     let feeds: &DisplayedFeeds = &feeds.read();
 
     let feed_nodes = feeds.feeds.iter().map(|feed| {
-        let title = feed.stored.title.clone().unwrap_or_default();
-
         let id = feed.stored.feed_id.clone();
-        let title = feed.stored.title.clone().unwrap_or("untitled".to_string());
-        //let url = feed.stored.url.clone();
+        let title = feed.stored.display_title().unwrap_or("untitled").to_string();
+        let title_override = feed.stored.title_override.clone().unwrap_or_default();
+        let url = feed.stored.url.clone();
+        let remove_question = format!("Remove {} and all its entries?", title);
         let key = id.0.clone();
-        let enabled = if feed.enabled { "x" } else { "v" };
+        let paused = feed.stored.paused;
+        let pause = if paused { "resume" } else { "pause" };
+        // the intervals are in milliseconds, the user sees them in minutes
+        let poll_minutes = feed
+            .stored
+            .poll_interval_override
+            .map(|interval| (interval / 60_000).to_string())
+            .unwrap_or_default();
+        let poll_title = match feed.stored.poll_interval_override.or(feed.stored.poll_interval) {
+            Some(interval) => format!("fetched every {} minutes, change it", interval / 60_000),
+            None => "change how often this feed is fetched".to_string(),
+        };
+        let (badge, badge_color) = match feed.stored.health() {
+            FeedHealth::Ok => ("●", "green"),
+            FeedHealth::Failing => ("●", "orange"),
+            FeedHealth::Dead => ("✕", "red"),
+        };
+        let health = match &feed.stored.last_error {
+            Some(error) if feed.stored.failures > 0 => format!(
+                "failed {} times in a row, last time: {}",
+                feed.stored.failures, error.message
+            ),
+            _ => "refreshed fine".to_string(),
+        };
 
         rsx!(
         p {
@@ -202,17 +280,84 @@ pub fn Feeds(cx: Scope) -> Element {
                     "{title}"
                 } */
                 div {
-                    "{title}"
+                    span {
+                        class: "{badge_color}",
+                        title: "{health}",
+                        "{badge} "
+                    }
+                    a {
+                        href: "#",
+                        title: "show entries of this feed only",
+                        onclick: move |_| entries_handle.read().handle_fetch_feed_entries(id),
+                        "{title}"
+                    }
                     button {
-                        //disabled: "{read}",
+                        title: "stop or resume refreshing this feed",
+                        onclick: move |_| feeds_handle.read().handle_update_feed(UpdateFeed {
+                            feed_id: id,
+                            paused: Some(!paused),
+                            ..Default::default()
+                        }),
+                        "{pause}"
+                    }
+                    button {
+                        title: "rename this feed, an empty title brings back its own",
+                        onclick: move |_| {
+                            if let Some(title) = ask("Feed title", &title_override) {
+                                feeds_handle.read().handle_update_feed(UpdateFeed {
+                                    feed_id: id,
+                                    title_override: Some(title),
+                                    ..Default::default()
+                                });
+                            }
+                        },
+                        "rename"
+                    }
+                    button {
+                        title: "{poll_title}",
                         onclick: move |_| {
-                            let feeds = use_atom_ref(&cx, FEEDS).clone();
-                            let mut feeds = feeds.write();
-                            if let Some(pos) = feeds.feeds.iter().position(|f| f.stored.feed_id == id) {
-                                feeds.feeds.get_mut(pos).unwrap().enabled = !feeds.feeds.get(pos).unwrap().enabled;
+                            let question = "Minutes between the fetches, empty to let it be learned";
+                            if let Some(minutes) = ask(question, &poll_minutes) {
+                                // anything but a number of minutes goes back to the learned interval
+                                let minutes = minutes.trim().parse::<u64>().unwrap_or(0);
+                                feeds_handle.read().handle_update_feed(UpdateFeed {
+                                    feed_id: id,
+                                    poll_interval: Some(minutes * 60_000),
+                                    ..Default::default()
+                                });
                             }
                         },
-                        "{enabled}"
+                        "poll"
+                    }
+                    button {
+                        title: "change the address of this feed",
+                        onclick: move |_| {
+                            if let Some(url) = ask("Feed URL", &url) {
+                                feeds_handle.read().handle_update_feed(UpdateFeed {
+                                    feed_id: id,
+                                    url: Some(url),
+                                    ..Default::default()
+                                });
+                            }
+                        },
+                        "url"
+                    }
+                    button {
+                        title: "mark read all the entries of this feed",
+                        onclick: move |_| entries_handle.read().handle_mark_all_read(MarkAllRead {
+                            feed_id: Some(id),
+                            older_than: None,
+                        }),
+                        "read all"
+                    }
+                    button {
+                        title: "unsubscribe from this feed",
+                        onclick: move |_| {
+                            if confirm(&remove_question) {
+                                feeds_handle.read().handle_remove_feed(id);
+                            }
+                        },
+                        "remove"
                     }
                 }
             }
@@ -224,6 +369,62 @@ pub fn Feeds(cx: Scope) -> Element {
     })
 }
 
+/// Asks the user for a value, returns None if the dialog is cancelled
+pub fn ask(message: &str, default: &str) -> Option<String> {
+    web_sys::window()?
+        .prompt_with_message_and_default(message, default)
+        .ok()
+        .flatten()
+}
+
+fn confirm(message: &str) -> bool {
+    web_sys::window()
+        .and_then(|window| window.confirm_with_message(message).ok())
+        .unwrap_or(false)
+}
+
+/// Adds the feed by its URL, or by the URL of a page linking it,
+/// in which case the feeds found are listed to pick one of them
+#[allow(non_snake_case)]
+pub fn AddFeedForm(cx: Scope) -> Element {
+    let feeds_handle = use_context::<FeedsHandle>(&cx).unwrap().to_owned();
+    let candidates = use_atom_ref(&cx, FEED_CANDIDATES);
+    let url = use_state(&cx, String::new);
+
+    let candidate_nodes = candidates.read().clone().into_iter().map(|candidate| {
+        let title = candidate.title.clone().unwrap_or(candidate.url.clone());
+        let FeedCandidate {
+            url, content_type, ..
+        } = candidate;
+        rsx!(
+            li {
+                key: "{url}",
+                a {
+                    href: "#",
+                    title: "{content_type}",
+                    onclick: move |_| feeds_handle.read().handle_add_feed(url.clone()),
+                    "{title}"
+                }
+            }
+        )
+    });
+
+    cx.render(rsx! {
+        div {
+            input {
+                placeholder: "Feed or site URL",
+                value: "{url}",
+                oninput: move |ev| url.set(ev.value.clone()),
+            }
+            button {
+                onclick: move |_| feeds_handle.read().handle_add_feed(url.get().clone()),
+                "Add"
+            }
+            ul { candidate_nodes }
+        }
+    })
+}
+
 //pub fn handle_refresh(cx: &Scope) {
 //let login_data = use_read(cx, LOGIN_SETTINGS);
 //let login_data: LoginSettings = LoginSettings {
@@ -1,5 +1,6 @@
 mod aws;
 mod entries;
+mod errors;
 mod feeds;
 //mod util;
 
@@ -12,6 +13,7 @@ use worker::WorkerStatus;
 
 use crate::aws::*;
 use crate::entries::*;
+use crate::errors::*;
 use crate::feeds::*;
 
 //use crate::util::*;
@@ -42,6 +44,7 @@ fn app(cx: Scope) -> Element {
                 class: "fl w-100 pa2",
 
                 AwsSettingsForm {},
+                ErrorBanner {},
                 div {
                     // a row with buttons
                     class: "fl w-100",
@@ -67,6 +70,7 @@ fn app(cx: Scope) -> Element {
                     },
                         "Settings"
                     },
+                    RefreshSummary {}
                 }
                 div {
                     // a row with main panel: feeds and entries
@@ -75,13 +79,16 @@ fn app(cx: Scope) -> Element {
                     div {
                         class: "fl w-20",
                         //style: "height: 80vh",
+                        AddFeedForm {}
                         nav {
                            Feeds {}
                         }
                     }
                     div {
+                        id: "entries",
                         class: "fl w-60",
                         style: "overflow: scroll; height: 80vh",
+                        onscroll: move |_| entries_handle.read().handle_scroll("entries"),
 
                         main { Entries {} }
                     }
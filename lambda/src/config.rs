@@ -0,0 +1,100 @@
+//! the deployment specific settings, read from the lambda environment variables
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::fetch::FetchOptions;
+use crate::schedule::ScheduleOptions;
+
+/// names of the DynamoDB tables and indexes, the defaults are the names used
+/// by the original deployment; and the settings of fetching feeds
+#[derive(Debug, Clone)]
+pub struct Config {
+    // ENTRIES_TABLE
+    pub entries_table: String,
+    // FEEDS_TABLE
+    pub feeds_table: String,
+    // ENTRIES_READ_STATUS_INDEX, keyed by readTs and sorted by published
+    pub read_status_index: String,
+    // ENTRIES_FEED_INDEX, keyed by feedId and sorted by published
+    pub feed_index: String,
+    // FEED_USER_AGENT, FEED_MAX_REDIRECTS, FEED_CONNECT_TIMEOUT and FEED_TIMEOUT in seconds,
+    // FEED_MAX_BODY_SIZE in bytes, FEED_ALLOWED_HOSTS separated by commas
+    pub fetch: FetchOptions,
+    // FEED_MIN_POLL_INTERVAL and FEED_MAX_POLL_INTERVAL in minutes
+    pub schedule: ScheduleOptions,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            entries_table: "albibek-rss-entries".to_string(),
+            feeds_table: "albibek-rss-feeds".to_string(),
+            read_status_index: "entry-read-status".to_string(),
+            feed_index: "entry-feed".to_string(),
+            fetch: FetchOptions::default(),
+            schedule: ScheduleOptions::default(),
+        }
+    }
+}
+
+impl Config {
+    /// the region is not here, the AWS SDK takes it from AWS_REGION set by the lambda runtime
+    pub fn from_env() -> Self {
+        let default = Config::default();
+        let var = |name: &str, default: String| {
+            std::env::var(name)
+                .ok()
+                .filter(|value| !value.is_empty())
+                .unwrap_or(default)
+        };
+        let fetch = default.fetch;
+        let minutes = |name: &str, default: u64| parse_var(name, default / 60_000) * 60_000;
+        let mut schedule = ScheduleOptions {
+            min_interval: minutes("FEED_MIN_POLL_INTERVAL", default.schedule.min_interval),
+            max_interval: minutes("FEED_MAX_POLL_INTERVAL", default.schedule.max_interval),
+        };
+        if schedule.min_interval > schedule.max_interval {
+            warn!(
+                ?schedule,
+                "FEED_MIN_POLL_INTERVAL is above FEED_MAX_POLL_INTERVAL, the defaults are used"
+            );
+            schedule = default.schedule;
+        }
+        Config {
+            entries_table: var("ENTRIES_TABLE", default.entries_table),
+            feeds_table: var("FEEDS_TABLE", default.feeds_table),
+            read_status_index: var("ENTRIES_READ_STATUS_INDEX", default.read_status_index),
+            feed_index: var("ENTRIES_FEED_INDEX", default.feed_index),
+            fetch: FetchOptions {
+                user_agent: var("FEED_USER_AGENT", fetch.user_agent),
+                max_redirects: parse_var("FEED_MAX_REDIRECTS", fetch.max_redirects),
+                connect_timeout: Duration::from_secs(parse_var(
+                    "FEED_CONNECT_TIMEOUT",
+                    fetch.connect_timeout.as_secs(),
+                )),
+                timeout: Duration::from_secs(parse_var("FEED_TIMEOUT", fetch.timeout.as_secs())),
+                max_body_size: parse_var("FEED_MAX_BODY_SIZE", fetch.max_body_size),
+                allowed_hosts: var("FEED_ALLOWED_HOSTS", String::new())
+                    .split(',')
+                    .map(|host| host.trim().to_string())
+                    .filter(|host| !host.is_empty())
+                    .collect(),
+            },
+            schedule,
+        }
+    }
+}
+
+/// numbers which cannot be parsed are reported and replaced by the defaults
+fn parse_var<T: FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) if !value.is_empty() => value.trim().parse().unwrap_or_else(|_| {
+            warn!(name, ?value, "invalid number in the environment variable");
+            default
+        }),
+        _ => default,
+    }
+}
@@ -0,0 +1,194 @@
+//! finding the feeds linked from HTML pages, for the users adding a site by its address
+
+use url::Url;
+
+use model::feed::FeedCandidate;
+
+// types of <link rel="alternate"> pointing to feeds, JSON Feed used to be plain JSON
+const FEED_TYPES: [&str; 5] = [
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/rdf+xml",
+    "application/feed+json",
+    "application/json",
+];
+
+/// tells whether the fetched document is an HTML page rather than a feed
+pub fn is_html(content_type: Option<&str>, body: &[u8]) -> bool {
+    if let Some(content_type) = content_type {
+        if content_type.to_lowercase().contains("html") {
+            return true;
+        }
+    }
+    let head = String::from_utf8_lossy(&body[..body.len().min(1024)]).to_lowercase();
+    let head = head.trim_start_matches(|c: char| c.is_whitespace() || c == '\u{feff}');
+    head.starts_with("<!doctype html") || head.starts_with("<html")
+}
+
+/// returns the feeds the page links with `<link rel="alternate">`, in the order of the page;
+/// relative links are resolved against the page url
+pub fn discover_feeds(page_url: &str, html: &str) -> Vec<FeedCandidate> {
+    let base = match Url::parse(page_url) {
+        Ok(base) => base,
+        Err(_) => return Vec::new(),
+    };
+    // lowercasing ASCII keeps the positions, so they can be used in the original
+    let lowercase = html.to_ascii_lowercase();
+    let mut candidates: Vec<FeedCandidate> = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = lowercase[pos..].find("<link") {
+        let start = pos + start + "<link".len();
+        let end = match lowercase[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        pos = end;
+        // not a <link> tag, but e.g. <linkage>
+        if !html[start..].starts_with(|c: char| c.is_whitespace()) {
+            continue;
+        }
+
+        let attributes = attributes(&html[start..end]);
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(attribute, _)| attribute == name)
+                .map(|(_, value)| value.trim())
+        };
+        let alternate = attribute("rel").map_or(false, |rel| {
+            rel.split_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("alternate"))
+        });
+        let content_type = attribute("type")
+            .and_then(|content_type| content_type.split(';').next())
+            .map(|content_type| content_type.trim().to_lowercase())
+            .unwrap_or_default();
+        if !alternate || !FEED_TYPES.contains(&content_type.as_str()) {
+            continue;
+        }
+        let url = match attribute("href").map(|href| base.join(href)) {
+            Some(Ok(url)) => url.to_string(),
+            _ => continue,
+        };
+        if candidates.iter().any(|candidate| candidate.url == url) {
+            continue;
+        }
+        candidates.push(FeedCandidate {
+            url,
+            title: attribute("title")
+                .filter(|title| !title.is_empty())
+                .map(String::from),
+            content_type,
+        });
+    }
+    candidates
+}
+
+/// parses the attributes of a tag, names are lowercased and values are unquoted and unescaped
+fn attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        if name_end == 0 {
+            break;
+        }
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let mut value = "";
+        if let Some(assigned) = rest.strip_prefix('=') {
+            let assigned = assigned.trim_start();
+            match assigned.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let quoted = &assigned[1..];
+                    let end = quoted.find(quote).unwrap_or(quoted.len());
+                    value = &quoted[..end];
+                    rest = quoted.get(end + 1..).unwrap_or_default();
+                }
+                _ => {
+                    let end = assigned.find(char::is_whitespace).unwrap_or(assigned.len());
+                    value = &assigned[..end];
+                    rest = &assigned[end..];
+                }
+            }
+        }
+        attributes.push((name, unescape(value)));
+    }
+    attributes
+}
+
+/// replaces the character references common in attributes
+fn unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+  <title>A blog</title>
+  <link rel="stylesheet" href="/style.css">
+  <LINK REL="alternate" TYPE="application/rss+xml" TITLE="Posts &amp; notes" HREF="/feed.xml">
+  <link rel='alternate' type='application/atom+xml' href='https://example.org/atom.xml' />
+  <link rel=alternate type="application/feed+json; charset=utf-8" href=feed.json>
+  <link rel="alternate" type="text/html" hreflang="de" href="/de/">
+  <link rel="alternate" type="application/rss+xml" href="/feed.xml">
+  <linkage rel="alternate" type="application/rss+xml" href="/not-a-link.xml">
+</head>
+<body></body>
+</html>"#;
+
+    #[test]
+    fn test_discover_feeds() {
+        let candidates = discover_feeds("https://example.org/blog/", PAGE);
+        assert_eq!(
+            candidates,
+            vec![
+                FeedCandidate {
+                    url: "https://example.org/feed.xml".to_string(),
+                    title: Some("Posts & notes".to_string()),
+                    content_type: "application/rss+xml".to_string(),
+                },
+                FeedCandidate {
+                    url: "https://example.org/atom.xml".to_string(),
+                    title: None,
+                    content_type: "application/atom+xml".to_string(),
+                },
+                FeedCandidate {
+                    url: "https://example.org/blog/feed.json".to_string(),
+                    title: None,
+                    content_type: "application/feed+json".to_string(),
+                },
+            ]
+        );
+        assert!(discover_feeds("https://example.org/", "<html></html>").is_empty());
+    }
+
+    #[test]
+    fn test_is_html() {
+        assert!(is_html(Some("text/html; charset=utf-8"), b""));
+        assert!(is_html(None, PAGE.as_bytes()));
+        assert!(is_html(None, b"\n  <html lang=\"en\">"));
+        assert!(!is_html(
+            Some("application/rss+xml"),
+            b"<rss version=\"2.0\">"
+        ));
+        assert!(!is_html(
+            None,
+            b"{\"version\": \"https://jsonfeed.org/version/1.1\"}"
+        ));
+    }
+}
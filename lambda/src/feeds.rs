@@ -1,270 +1,1622 @@
-use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::task::spawn;
 
 use anyhow::{anyhow, Error};
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 
-use aws_sdk_dynamodb::{
-    model::AttributeAction, model::AttributeValue, model::AttributeValueUpdate, model::ReturnValue,
-    types::SdkError, Client,
-};
-use aws_types::sdk_config::SdkConfig;
-
-use serde_dynamo::{from_items, to_attribute_value, to_item};
-
-use rss::Channel;
-
-use hyper::{body::Buf, body::Bytes, client::Client as HttpClient, Body, Uri};
-use hyper_rustls::HttpsConnectorBuilder;
+use hyper::body::{Buf, Bytes};
+use hyper::StatusCode;
 
 use model::entry::StoredEntry;
 use model::events::*;
 use model::feed::*;
+use model::jsonfeed::{JsonFeed, JsonFeedItem};
+use model::rdf::RdfChannel;
 use model::util::*;
 
-pub async fn all_feeds(_: ()) -> Result<Response, Error> {
-    let feeds = get_all_feeds().await?;
+use crate::config::Config;
+use crate::discover::{discover_feeds, is_html};
+use crate::fetch::*;
+use crate::schedule::{learn_poll_interval, HISTORY};
+use crate::storage::{PutOutcome, Storage};
+
+pub async fn all_feeds(store: Arc<dyn Storage>, _: ()) -> Result<Response, Error> {
+    let feeds = store.all_feeds().await?;
     Ok(Response::AllFeeds(feeds))
 }
 
-pub async fn get_all_feeds() -> Result<Vec<StoredFeed>, Error> {
-    let shared_config = aws_config::load_from_env().await;
-    let client = Client::new(&shared_config);
-    let result = client.scan().table_name("albibek-rss-feeds").send().await?;
-    if let Some(items) = result.items {
-        Ok(from_items(items)?)
+// the number of entries returned by the preview of a feed
+const PREVIEW_ENTRIES: usize = 10;
+
+pub async fn add_feed(
+    store: Arc<dyn Storage>,
+    config: Arc<Config>,
+    feed_url: String,
+) -> Result<Response, Error> {
+    let (mut feed, parsed) = match fetch_new_feed(&config, feed_url).await? {
+        NewFeed::Feed(feed, parsed) => (*feed, parsed),
+        NewFeed::Page(candidates) => return Ok(Response::FeedCandidates(candidates)),
+    };
+    // the feed stored has the user's settings and its health, which a new one would lose
+    if store.feed(feed.feed_id).await?.is_some() {
+        return Err(ErrorResponse::new(
+            ErrorCode::BadRequest,
+            format!("{} feed is already added", feed.url),
+        )
+        .into());
+    }
+
+    // the entries are stored right away, the next refresh skips the feed if it is not modified
+    let counts = put_entries(store.clone(), parsed.entries).await?;
+    if counts.failed > 0 {
+        // so the entries failed are stored by the next refresh
+        feed.forget_validators();
+    }
+    store.put_feed(&feed).await?;
+    debug!(?feed, "feed added");
+    Ok(Response::Ok(OkResponse::new(format!(
+        "added  {} feed",
+        feed.url
+    ))))
+}
+
+pub async fn preview_feed(config: Arc<Config>, feed_url: String) -> Result<Response, Error> {
+    let (feed, parsed) = match fetch_new_feed(&config, feed_url).await? {
+        NewFeed::Feed(feed, parsed) => (*feed, parsed),
+        NewFeed::Page(candidates) => return Ok(Response::FeedCandidates(candidates)),
+    };
+
+    let ParsedFeed {
+        mut entries,
+        errors,
+    } = parsed;
+    let total_entries = entries.len();
+    entries.truncate(PREVIEW_ENTRIES);
+    Ok(Response::FeedPreview(FeedPreview {
+        feed,
+        entries,
+        total_entries,
+        errors,
+    }))
+}
+
+pub async fn remove_feed(store: Arc<dyn Storage>, request: RemoveFeed) -> Result<Response, Error> {
+    let feed_id = request.feed_id;
+    if store.feed(feed_id).await?.is_none() {
+        return Err(feed_not_found(feed_id).into());
+    }
+    // the entries go first, so the removal could be retried if it fails half way
+    let removed = if request.remove_entries {
+        store.remove_feed_entries(feed_id).await?
     } else {
-        Ok(Vec::new())
+        0
+    };
+    store.remove_feed(feed_id).await?;
+    info!(?feed_id, removed, "feed removed");
+    Ok(Response::Ok(OkResponse::new(format!(
+        "removed feed {} and {} entries",
+        feed_id.0, removed
+    ))))
+}
+
+pub async fn update_feed(
+    store: Arc<dyn Storage>,
+    config: Arc<Config>,
+    request: UpdateFeed,
+) -> Result<Response, Error> {
+    let mut feed = store
+        .feed(request.feed_id)
+        .await?
+        .ok_or_else(|| feed_not_found(request.feed_id))?;
+
+    if let Some(url) = request.url.filter(|url| *url != feed.url) {
+        let valid = url::Url::parse(&url).map_or(false, |url| {
+            matches!(url.scheme(), "http" | "https") && url.has_host()
+        });
+        if !valid {
+            return Err(ErrorResponse::new(
+                ErrorCode::BadRequest,
+                format!("{} is not an http(s) url", url),
+            )
+            .into());
+        }
+        // the feed keeps its id, so its entries stay with it;
+        // the validators and the schedule belong to the old address
+        feed.url = url;
+        feed.etag = None;
+        feed.last_modified = None;
+        feed.body_hash = None;
+        feed.next_fetch = None;
+    }
+    if let Some(title) = request.title_override {
+        feed.title_override = Some(title.trim().to_string()).filter(|title| !title.is_empty());
     }
+    if let Some(paused) = request.paused {
+        feed.paused = paused;
+    }
+    if let Some(interval) = request.poll_interval {
+        // the feed is due with the next refresh, so the new interval applies right away
+        feed.poll_interval_override = Some(interval)
+            .filter(|interval| *interval > 0)
+            .map(|interval| config.schedule.clamp(interval));
+        feed.next_fetch = None;
+    }
+
+    store.put_feed(&feed).await?;
+    debug!(?feed, "feed updated");
+    Ok(Response::Ok(OkResponse::new(format!(
+        "updated {} feed",
+        feed.url
+    ))))
+}
+
+fn feed_not_found(feed_id: FeedID) -> ErrorResponse {
+    ErrorResponse::new(
+        ErrorCode::BadRequest,
+        format!("feed {} is not found", feed_id.0),
+    )
+}
+
+/// what is found at the address of a feed to be added
+enum NewFeed {
+    Feed(Box<StoredFeed>, ParsedFeed),
+    // the address of a site was given, rather than of its feed
+    Page(Vec<FeedCandidate>),
 }
 
-pub async fn add_feed(feed_url: String) -> Result<Response, Error> {
-    let config = aws_config::load_from_env().await;
-    let result = fetch_feed(&feed_url).await?;
-    let rss_reader = result.clone().reader();
-    //let mut entries = HashMap::new();
+/// fetches and parses the feed which is not stored yet
+async fn fetch_new_feed(config: &Config, feed_url: String) -> Result<NewFeed, Error> {
+    let result = fetch_feed(&feed_url, None, None, &config.fetch)
+        .await
+        .and_then(|fetched| fetched.ok_or_else(|| anyhow!("unexpected not modified response")))
+        .map_err(|e| match e.downcast::<ErrorResponse>() {
+            // the url is not allowed to be fetched
+            Ok(response) => response,
+            Err(e) => ErrorResponse::new(
+                ErrorCode::FeedFetchFailed,
+                format!("could not fetch {}: {:#}", feed_url, e),
+            ),
+        })?;
+
+    let format = FeedFormat::detect(result.content_type.as_deref(), &result.body);
+    if format.is_none() && is_html(result.content_type.as_deref(), &result.body) {
+        let candidates = discover_feeds(&result.url, &String::from_utf8_lossy(&result.body));
+        debug!(?candidates, "feeds linked from the page");
+        if candidates.is_empty() {
+            return Err(ErrorResponse::new(
+                ErrorCode::ParseFailed,
+                format!("{} is a page without any feeds linked", feed_url),
+            )
+            .into());
+        }
+        return Ok(NewFeed::Page(candidates));
+    }
 
+    // the feed is known by its new location, if the one given has moved
+    let feed_url = result.moved_to.clone().unwrap_or(feed_url);
     let now = chrono::Utc::now();
     let mut feed = StoredFeed {
         feed_id: FeedID(hash(feed_url.as_bytes())),
         last_update: now.timestamp_millis() as u64,
         url: feed_url.clone(),
-        title: None,
-        description: None,
-        ext_last_update: None,
+        ..Default::default()
+    };
+    result.remember_validators(&mut feed);
+    let parsed = parse_feed_items(result, &mut feed).map_err(|e| {
+        warn!(error = ?e, "could not update feed");
+        ErrorResponse::new(
+            ErrorCode::ParseFailed,
+            format!("{} is not a supported feed: {:#}", feed_url, e),
+        )
+    })?;
+    feed.poll_interval = Some(learn_poll_interval(
+        &parsed.entries,
+        now.timestamp_millis() as u64,
+        &config.schedule,
+    ));
+    feed.schedule_next_fetch(now);
+    Ok(NewFeed::Feed(Box::new(feed), parsed))
+}
+
+pub async fn refresh_feeds(
+    store: Arc<dyn Storage>,
+    config: Arc<Config>,
+    request: RefreshFeeds,
+) -> Result<Response, Error> {
+    let feeds = store.all_feeds().await?;
+    let now = chrono::Utc::now();
+    // paused feeds are not refreshed even when forced
+    let (paused, feeds): (Vec<_>, Vec<_>) = feeds.into_iter().partition(|feed| feed.paused);
+    let (feeds, skipped): (Vec<_>, Vec<_>) = feeds
+        .into_iter()
+        .partition(|feed| request.force || feed.is_due(now));
+    let mut tasks = Vec::new();
+    for feed in feeds {
+        // reported if the worker fails
+        let report = feed_report(&feed, RefreshOutcome::NotModified);
+        let store = store.clone();
+        let config = config.clone();
+        let handle = spawn(async move { feed_worker(store, config, feed).await });
+        tasks.push((report, handle));
+    }
+    let mut reports = Vec::new();
+    for (report, task) in tasks {
+        let error = match task.await {
+            Ok(Ok(report)) => {
+                reports.push(report);
+                continue;
+            }
+            Ok(Err(e)) => e,
+            Err(e) => e.into(),
+        };
+        warn!(error = ?error, feed = ?report.title, "error refreshing feed");
+        reports.push(FeedReport {
+            outcome: RefreshOutcome::Failed(FeedError {
+                kind: FeedErrorKind::Storage,
+                message: format!("{:#}", error),
+                time: now.timestamp_millis() as u64,
+            }),
+            ..report
+        });
+    }
+    reports.extend(
+        skipped
+            .iter()
+            .chain(paused.iter())
+            .map(|feed| feed_report(feed, RefreshOutcome::Skipped)),
+    );
+    Ok(Response::RefreshReport(RefreshReport { feeds: reports }))
+}
+
+/// the report of the feed with nothing done
+fn feed_report(feed: &StoredFeed, outcome: RefreshOutcome) -> FeedReport {
+    FeedReport {
+        feed_id: feed.feed_id,
+        title: feed.display_title().unwrap_or(&feed.url).to_string(),
+        outcome,
+        new_entries: 0,
+        updated_entries: 0,
+        dropped: Vec::new(),
+        duration_ms: 0,
+    }
+}
+
+/// fetches and parses the feed, storing new and changed entries;
+/// nothing is parsed or written, except the feed itself, when the feed has not changed
+pub async fn feed_worker(
+    store: Arc<dyn Storage>,
+    config: Arc<Config>,
+    feed: StoredFeed,
+) -> Result<FeedReport, Error> {
+    let started = std::time::Instant::now();
+    let now = chrono::Utc::now();
+    let mut new_feed = feed.clone();
+    // the outcome is recorded in the feed, so failing feeds could be seen and backed off
+    let (outcome, counts, dropped) = match fetch_feed_update(&config, &mut new_feed).await {
+        Ok((status, None)) => {
+            new_feed.record_success(now.timestamp_millis() as u64, status);
+            // the interval grows while the feed stays quiet, so it is learned again
+            // from the entries stored, unless the user's one is used anyway
+            if new_feed.poll_interval_override.is_none() {
+                let request = AllEntries {
+                    feed_id: Some(new_feed.feed_id),
+                    ..Default::default()
+                };
+                let (entries, _) = store.entries(&request, HISTORY as u32).await?;
+                new_feed.poll_interval = Some(learn_poll_interval(
+                    &entries,
+                    now.timestamp_millis() as u64,
+                    &config.schedule,
+                ));
+            }
+            (
+                RefreshOutcome::NotModified,
+                PutCounts::default(),
+                Vec::new(),
+            )
+        }
+        Ok((status, Some(ParsedFeed { entries, errors }))) => {
+            new_feed.record_success(now.timestamp_millis() as u64, status);
+            // the entries the feed has now tell how often it should be fetched
+            new_feed.poll_interval = Some(learn_poll_interval(
+                &entries,
+                now.timestamp_millis() as u64,
+                &config.schedule,
+            ));
+            let counts = put_entries(store.clone(), entries).await?;
+            let outcome = match &counts.error {
+                None => RefreshOutcome::Fetched,
+                Some(error) => {
+                    // the feed is parsed again next time, so the entries failed are retried
+                    new_feed.forget_validators();
+                    RefreshOutcome::Failed(FeedError {
+                        kind: FeedErrorKind::Storage,
+                        message: format!(
+                            "{} entries could not be stored: {}",
+                            counts.failed, error
+                        ),
+                        time: now.timestamp_millis() as u64,
+                    })
+                }
+            };
+            (outcome, counts, errors)
+        }
+        Err((kind, status, e)) => {
+            warn!(error = ?e, url = ?feed.url, ?kind, "error refreshing feed");
+            let error = FeedError {
+                kind,
+                message: format!("{:#}", e),
+                time: now.timestamp_millis() as u64,
+            };
+            new_feed.record_failure(error.clone(), status);
+            (
+                RefreshOutcome::Failed(error),
+                PutCounts::default(),
+                Vec::new(),
+            )
+        }
     };
-    let ok;
-    match rss::Channel::read_from(rss_reader) {
-        Ok(channel) => {
-            ok = true;
-            feed.update_from_rss_channel(channel).map_err(|e| {
-                warn!(error = ?e, "could not update feed from rss");
-                e
-            })?;
+
+    new_feed.last_update = now.timestamp_millis() as u64;
+    new_feed.schedule_next_fetch(now);
+    debug!(feed = ?new_feed, "put");
+    store.put_feed(&new_feed).await?;
+    Ok(FeedReport {
+        new_entries: counts.new,
+        updated_entries: counts.updated,
+        dropped,
+        duration_ms: started.elapsed().as_millis() as u64,
+        // the title is known once the feed is parsed
+        ..feed_report(&new_feed, outcome)
+    })
+}
+
+/// fetches the feed, returns the HTTP status of the response and the parsed feed, if it
+/// has changed; errors come along with their kind and the status, if the server has answered
+async fn fetch_feed_update(
+    config: &Config,
+    feed: &mut StoredFeed,
+) -> Result<(u16, Option<ParsedFeed>), FeedFailure> {
+    let result = fetch_feed(
+        &feed.url,
+        feed.etag.as_deref(),
+        feed.last_modified.as_deref(),
+        &config.fetch,
+    )
+    .await
+    .map_err(|e| {
+        let status = e.downcast_ref::<StatusError>().map(|e| e.0.as_u16());
+        let kind = match status {
+            Some(_) => FeedErrorKind::Status,
+            None => FeedErrorKind::Fetch,
+        };
+        (kind, status, e)
+    })?;
+
+    if let Some(moved_to) = result.as_ref().and_then(|result| result.moved_to.clone()) {
+        info!(from = ?feed.url, to = ?moved_to, "feed has moved");
+        feed.url = moved_to;
+    }
+    let status = StatusCode::OK.as_u16();
+    match result {
+        None => {
+            debug!(url = ?feed.url, "feed is not modified");
+            Ok((StatusCode::NOT_MODIFIED.as_u16(), None))
+        }
+        Some(result) if feed.body_hash == Some(result.body_hash()) => {
+            debug!(url = ?feed.url, "feed body is not changed");
+            result.remember_validators(feed);
+            Ok((status, None))
         }
-        Err(e) => {
-            warn!(error = ?e, "could not update feed from rss");
-            ok = false;
-            //return Err(e.into());
+        Some(result) => {
+            // the validators are kept only if the feed is parsed,
+            // otherwise it would not be parsed again until it changes
+            let parsed = parse_feed_items(result.clone(), feed)
+                .map_err(|e| (FeedErrorKind::Parse, Some(status), e))?;
+            result.remember_validators(feed);
+            Ok((status, Some(parsed)))
         }
     }
+}
 
-    if ok {
-        let dynamo = Client::new(&config);
-        let item: HashMap<String, AttributeValue> = to_item(&feed)?;
+// the kind of the error, the HTTP status if the server has answered, and the error itself
+type FeedFailure = (FeedErrorKind, Option<u16>, Error);
 
-        let resp = dynamo
-            .put_item()
-            .table_name("albibek-rss-feeds")
-            .set_item(Some(to_item(&feed)?))
-            //.return_consumed_capacity(aws_sdk_dynamodb::model::ReturnConsumedCapacity::Total)
-            //.return_values(aws_sdk_dynamodb::model::ReturnValue::AllOld)
-            .send()
-            .await
-            .map_err(|e| {
-                warn!(error = ?e, "error putting feed");
-                e
-            })?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedFormat {
+    Rss,
+    Rdf,
+    Atom,
+    Json,
+}
+
+impl FeedFormat {
+    /// sniffs the format from the document itself, falling back to the content type
+    /// because servers often return text/xml or even text/plain for any kind of feed
+    pub fn detect(content_type: Option<&str>, body: &[u8]) -> Option<Self> {
+        let start = body
+            .iter()
+            .position(|b| !b.is_ascii_whitespace() && !b"\xef\xbb\xbf".contains(b))
+            .unwrap_or(0);
+        let head = &body[start..body.len().min(start + 2048)];
+        if head.first() == Some(&b'{') {
+            return Some(FeedFormat::Json);
+        }
+
+        // the first root element found wins, since the other names may be mentioned
+        // in namespace declarations
+        let head = String::from_utf8_lossy(head);
+        let sniffed = [
+            ("<rss", FeedFormat::Rss),
+            ("<rdf:RDF", FeedFormat::Rdf),
+            ("<feed", FeedFormat::Atom),
+        ]
+        .into_iter()
+        .filter_map(|(tag, format)| head.find(tag).map(|pos| (pos, format)))
+        .min_by_key(|(pos, _)| *pos)
+        .map(|(_, format)| format);
+        if sniffed.is_some() {
+            return sniffed;
+        }
 
-        debug!(?resp, ?item, "put feed response");
+        let content_type = content_type?.to_lowercase();
+        if content_type.contains("json") {
+            Some(FeedFormat::Json)
+        } else if content_type.contains("atom") {
+            Some(FeedFormat::Atom)
+        } else if content_type.contains("rdf") {
+            Some(FeedFormat::Rdf)
+        } else if content_type.contains("rss") {
+            Some(FeedFormat::Rss)
+        } else {
+            None
+        }
     }
+}
 
-    debug!(?ok, "fetch ok");
-    Ok(Response::Ok(OkResponse::new(format!(
-        "added  {} feed",
-        feed_url
-    ))))
+/// the entries of the feed, along with the errors of the items which are skipped
+#[derive(Debug, Default)]
+pub struct ParsedFeed {
+    pub entries: Vec<StoredEntry>,
+    pub errors: Vec<ItemError>,
 }
 
-pub async fn refresh_feeds(request: RefreshFeeds) -> Result<Response, Error> {
-    let shared_config = aws_config::load_from_env().await;
-    let feeds = get_all_feeds().await?;
-    let len = feeds.len();
-    let tasks = tokio::task::LocalSet::new();
-    for feed in feeds {
-        if !request.force {
-            // TODO don't update feed
+impl ParsedFeed {
+    fn push(&mut self, index: usize, entry: Result<StoredEntry, Error>) {
+        match entry {
+            Ok(entry) => self.entries.push(entry),
+            Err(e) => self.errors.push(ItemError {
+                index,
+                error: format!("{:#}", e),
+            }),
         }
-        let url = feed.url.clone();
-        let shared_config = shared_config.clone();
-        let handle = tokio::task::spawn(async move {
-            feed_worker(shared_config, feed)
-                .await
-                .map_err(|e| warn!("error fetching {:?}: {:?}", url, e))
-                .unwrap_or(());
-        });
-        tasks.spawn_local(handle);
     }
-    tasks.await;
-    Ok(Response::Ok(OkResponse::new(format!(
-        "updated {} feeds",
-        len
-    ))))
 }
 
-pub async fn feed_worker(config: SdkConfig, feed: StoredFeed) -> Result<(), Error> {
-    // TODO: set feed's last_update
-    let result = fetch_feed(&feed.url).await?;
+/// parses the fetched feed, updating the feed metadata and returning the entries found
+/// along with the errors of the items; if the format cannot be detected,
+/// all supported formats are tried in turn
+pub fn parse_feed_items(fetched: FetchedFeed, feed: &mut StoredFeed) -> Result<ParsedFeed, Error> {
+    let FetchedFeed {
+        content_type, body, ..
+    } = fetched;
+    let format = FeedFormat::detect(content_type.as_deref(), &body);
+    debug!(?format, ?content_type, "detected feed format");
+    if let Some(format) = format {
+        return parse_feed_as(format, body, feed);
+    }
 
-    let rss_reader = result.clone().reader();
-    let mut new_feed = feed.clone();
-    //let mut entries = HashMap::new();
-
-    if let Ok(mut channel) = rss::Channel::read_from(rss_reader) {
-        debug!("{:?}", channel);
-
-        let mut tasks = Vec::new();
-        while let Some(item) = channel.items.pop() {
-            let eitem = item.clone();
-            match StoredEntry::from_rss_item(feed.feed_id, item) {
-                Ok(entry) => {
-                    let expression_values = HashMap::from([(
-                        ":published".to_string(),
-                        to_attribute_value(&entry.published)?,
-                    )]);
-                    let dynamo_item = to_item(entry)?;
-                    let config = config.clone();
-                    let handle = spawn(async move {
-                        let dynamo = Client::new(&config);
-                        debug!(item = ?dynamo_item, "putting item");
-                        dynamo
-                            .put_item()
-                            .table_name("albibek-rss-entries")
-                            .set_item(Some(dynamo_item))
-                            .set_condition_expression(Some(
-                                "attribute_not_exists(entryId) OR published <> :published".into(),
-                            ))
-                            .set_expression_attribute_values(Some(expression_values))
-                            .set_return_values(Some(ReturnValue::None))
-                            .send()
-                            .await
-                            .map(|response| {
-                                debug!(response = ?response, "item written");
-                            })
-                            .unwrap_or_else(|e| {
-                                if let SdkError::ServiceError { ref err, .. } = e {
-                                    if err.is_conditional_check_failed_exception() {
-                                        return ();
-                                    }
-                                }
-                                warn!(error = ?e, "error writing item");
-                            });
-                    });
-                    tasks.push(handle);
+    for format in [
+        FeedFormat::Rss,
+        FeedFormat::Rdf,
+        FeedFormat::Atom,
+        FeedFormat::Json,
+    ] {
+        match parse_feed_as(format, body.clone(), feed) {
+            Ok(parsed) => return Ok(parsed),
+            Err(e) => debug!(error = ?e, ?format, "feed is not parsed"),
+        }
+    }
+    Err(anyhow!("bad feed"))
+}
+
+pub fn parse_feed_as(
+    format: FeedFormat,
+    data: Bytes,
+    feed: &mut StoredFeed,
+) -> Result<ParsedFeed, Error> {
+    let mut parsed = ParsedFeed::default();
+    match format {
+        FeedFormat::Rss => {
+            let mut channel = rss::Channel::read_from(data.reader())?;
+            debug!("{:?}", channel);
+            for (index, item) in channel.items.drain(..).enumerate() {
+                let eitem = item.clone();
+                let entry = StoredEntry::from_rss_item(feed.feed_id, item);
+                if let Err(e) = &entry {
+                    warn!(error = ?e, item = ?eitem, "error processing item");
                 }
-                Err(e) => {
+                parsed.push(index, entry);
+            }
+            feed.update_from_rss_channel(channel)?;
+        }
+        FeedFormat::Rdf => {
+            let mut channel = RdfChannel::read_from(data.reader())?;
+            debug!("{:?}", channel);
+            for (index, item) in channel.items.drain(..).enumerate() {
+                let eitem = item.clone();
+                let entry = StoredEntry::from_rdf_item(feed.feed_id, item);
+                if let Err(e) = &entry {
                     warn!(error = ?e, item = ?eitem, "error processing item");
-                    continue;
                 }
+                parsed.push(index, entry);
             }
+            feed.update_from_rdf_channel(channel)?;
         }
-        while let Some(task) = tasks.pop() {
-            task.await
-                .unwrap_or_else(|e| warn!(error = ?e, "error updating feed"));
+        FeedFormat::Atom => {
+            let mut atom = atom_syndication::Feed::read_from(data.reader())?;
+            debug!("{:?}", atom);
+            for (index, atom_entry) in atom.entries.drain(..).enumerate() {
+                let eentry = atom_entry.clone();
+                let entry = StoredEntry::from_atom_feed_entry(feed.feed_id, atom_entry);
+                if let Err(e) = &entry {
+                    warn!(error = ?e, entry = ?eentry, "error processing entry");
+                }
+                parsed.push(index, entry);
+            }
+            feed.update_from_atom_feed(atom)?;
         }
-        new_feed.update_from_rss_channel(channel)?;
-
-        //debug!("{:?}", entries);
-    } else {
-        let atom_reader = result.reader();
-        if let Ok(atom) = atom_syndication::Feed::read_from(atom_reader) {
-            new_feed.update_from_atom_feed(atom)?
-        } else {
-            return Err(anyhow!("bad feed"));
+        FeedFormat::Json => {
+            let mut json = JsonFeed::read_from(&data)?;
+            debug!("{:?}", json);
+            for (index, item) in json.items.drain(..).enumerate() {
+                let eitem = item.clone();
+                let entry = JsonFeedItem::from_value(item)
+                    .and_then(|item| StoredEntry::from_json_feed_item(feed.feed_id, item));
+                if let Err(e) = &entry {
+                    warn!(error = ?e, item = ?eitem, "error processing item");
+                }
+                parsed.push(index, entry);
+            }
+            feed.update_from_json_feed(json)?;
         }
-    };
-
-    let now = chrono::Utc::now();
-    //if feed.last_update == 0 ||
-    let dynamo = Client::new(&config);
-    new_feed.last_update = now.timestamp_millis() as u64;
-    debug!(feed = ?new_feed, "put");
-    let put_response = dynamo
-        .put_item()
-        .table_name("albibek-rss-feeds")
-        .set_item(Some(to_item(&new_feed)?))
-        //.set_condition_expression(Some("attribute_not_exists(feedId)".into()))
-        //.expression_attribute_values(
-        //"lastUpdate",
-        //AttributeValue::N(format!("{}", feed.last_update)),
-        //)
-        //.attribute_updates(
-        //"lastUpdate",
-        //AttributeValueUpdate::builder()
-        //.value(AttributeValue::N(format!("{}", now.timestamp_millis())))
-        //.action(AttributeAction::Put)
-        //.build(),
-        //)
-        .send()
-        .await
-        .map_err(|e| {
-            warn!(error = ?e, "error updating feed");
-            e
-        })?;
+    }
 
-    debug!(?put_response, "put feed response");
-    Ok(())
+    Ok(parsed)
 }
 
-pub async fn fetch_feed(url: &str) -> Result<Bytes, Error> {
-    let url = url.parse::<Uri>()?;
-    debug!("fetching {:?}", url);
-    let https = HttpsConnectorBuilder::new()
-        .with_native_roots()
-        .https_or_http()
-        .enable_http1()
-        .build();
-
-    let client: HttpClient<_, hyper::Body> = HttpClient::builder().build(https);
+/// what `put_entries` has done with the entries
+#[derive(Debug, Default)]
+pub struct PutCounts {
+    pub new: usize,
+    // entries changed by the feed since they were stored
+    pub updated: usize,
+    pub failed: usize,
+    // the error of one of the entries failed, if any
+    pub error: Option<String>,
+}
 
-    let res = client.get(url).await?;
-    if res.status() != 200 {
-        debug!("error fetching {:?}", res);
-        return Err(anyhow!("non-200 response"));
+/// writes the entries to the database in parallel; an entry failed doesn't stop the others,
+/// it is counted along with the new ones and the ones changed since they were stored
+pub async fn put_entries(
+    store: Arc<dyn Storage>,
+    entries: Vec<StoredEntry>,
+) -> Result<PutCounts, Error> {
+    let mut tasks = Vec::new();
+    for entry in entries {
+        let store = store.clone();
+        let handle = spawn(async move { store.put_entry(entry).await });
+        tasks.push(handle);
+    }
+    let mut counts = PutCounts::default();
+    while let Some(task) = tasks.pop() {
+        match task.await.map_err(Error::from).and_then(|put| put) {
+            Ok(PutOutcome::New) => counts.new += 1,
+            Ok(PutOutcome::Updated) => counts.updated += 1,
+            Ok(PutOutcome::Unchanged) => {}
+            Err(e) => {
+                warn!(error = ?e, "error writing item");
+                counts.failed += 1;
+                counts.error = Some(format!("{:#}", e));
+            }
+        }
     }
-    Ok(hyper::body::to_bytes(res).await?)
+    Ok(counts)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::init_tracing;
+    use model::entry::{Attachment, EntryID, PodcastInfo, PublishedSource, ENTRY_KEY_VERSION};
     use model::events::*;
 
+    use crate::storage::MemoryStorage;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response as HttpResponse, Server, StatusCode};
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const TEST_RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>Local feed</title>
+    <link>http://localhost/</link>
+    <description>Served by the test</description>
+    <item>
+      <title>First</title>
+      <link>http://localhost/1</link>
+      <guid>http://localhost/1</guid>
+      <pubDate>Tue, 10 Jun 2003 04:00:00 GMT</pubDate>
+    </item>
+  </channel>
+</rss>"#;
+
+    /// the test servers are local, so they have to be allowed explicitly
+    fn test_config() -> Arc<Config> {
+        let mut config = Config::default();
+        config.fetch.allowed_hosts = vec!["127.0.0.1".to_string()];
+        Arc::new(config)
+    }
+
+    fn test_feed() -> StoredFeed {
+        StoredFeed {
+            feed_id: FeedID(1),
+            url: "http://example.org/feed".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// parses the document as if it was fetched for the feed
+    fn parse_test_feed(
+        feed: &mut StoredFeed,
+        data: impl Into<Bytes>,
+        content_type: Option<&str>,
+    ) -> ParsedFeed {
+        let fetched = FetchedFeed {
+            content_type: content_type.map(str::to_string),
+            body: data.into(),
+            ..Default::default()
+        };
+        parse_feed_items(fetched, feed).unwrap()
+    }
+
+    /// serves the body on a random local port, so the feeds are fetched without the network
+    fn serve_feed(body: impl Into<Bytes>) -> String {
+        let body: Bytes = body.into();
+        let make_service = make_service_fn(move |_| {
+            let body = body.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_| {
+                    let body = body.clone();
+                    async move { Ok::<_, Infallible>(HttpResponse::new(Body::from(body))) }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}/feed.rss", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    /// serves the body with the ETag, answering "not modified" to the requests having it;
+    /// returns the url and the number of such answers
+    fn serve_cached_feed(body: &'static str, etag: &'static str) -> (String, Arc<AtomicUsize>) {
+        let not_modified = Arc::new(AtomicUsize::new(0));
+        let counter = not_modified.clone();
+        let make_service = make_service_fn(move |_| {
+            let counter = counter.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let counter = counter.clone();
+                    async move {
+                        let response = HttpResponse::builder().header(hyper::header::ETAG, etag);
+                        let matches = request
+                            .headers()
+                            .get(hyper::header::IF_NONE_MATCH)
+                            .map_or(false, |value| value == etag);
+                        let response = if matches {
+                            counter.fetch_add(1, Ordering::SeqCst);
+                            response
+                                .status(StatusCode::NOT_MODIFIED)
+                                .body(Body::empty())
+                        } else {
+                            response.body(Body::from(body))
+                        };
+                        Ok::<_, Infallible>(response.unwrap())
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}/feed.rss", server.local_addr());
+        tokio::spawn(server);
+        (url, not_modified)
+    }
+
+    #[tokio::test]
+    async fn test_conditional_fetch() {
+        init_tracing(true).unwrap();
+        let (url, not_modified) = serve_cached_feed(TEST_RSS, "\"v1\"");
+        let store = Arc::new(MemoryStorage::default());
+        let feed = StoredFeed {
+            feed_id: FeedID(1),
+            url,
+            ..Default::default()
+        };
+
+        let config = test_config();
+        feed_worker(store.clone(), config.clone(), feed)
+            .await
+            .unwrap();
+        let feed = store.all_feeds().await.unwrap().pop().unwrap();
+        assert_eq!(feed.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(feed.body_hash, Some(hash(TEST_RSS.as_bytes())));
+        assert_eq!(not_modified.load(Ordering::SeqCst), 0);
+
+        feed_worker(store.clone(), config, feed.clone())
+            .await
+            .unwrap();
+        assert_eq!(not_modified.load(Ordering::SeqCst), 1);
+        let refreshed = store.all_feeds().await.unwrap().pop().unwrap();
+        assert_eq!(refreshed.etag, feed.etag);
+        assert_eq!(refreshed.body_hash, feed.body_hash);
+    }
+
     #[tokio::test]
     async fn test_add_feed() {
         init_tracing(true).unwrap();
-        let event = "https://www.opennet.ru/opennews/opennews_all_utf.rss".to_string();
-        add_feed(event).await.unwrap();
-        let response = all_feeds(()).await.unwrap();
+        let store = Arc::new(MemoryStorage::default());
+        let event = serve_feed(TEST_RSS);
+        add_feed(store.clone(), test_config(), event.clone())
+            .await
+            .unwrap();
+        let (entries, _) = store.entries(&AllEntries::default(), 10).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        let response = all_feeds(store.clone(), ()).await.unwrap();
         debug!(?response, "all_feeds");
+        let feed = match response {
+            Response::AllFeeds(mut feeds) => {
+                assert_eq!(feeds.len(), 1);
+                assert_eq!(feeds[0].title.as_deref(), Some("Local feed"));
+                feeds.pop().unwrap()
+            }
+            response => panic!("unexpected response {:?}", response),
+        };
+
+        // adding the feed again leaves the one stored as it is
+        let paused = StoredFeed {
+            paused: true,
+            ..feed
+        };
+        store.put_feed(&paused).await.unwrap();
+        let e = add_feed(store.clone(), test_config(), event)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            e.downcast::<ErrorResponse>().unwrap().code,
+            ErrorCode::BadRequest
+        );
+        assert_eq!(store.feed(paused.feed_id).await.unwrap(), Some(paused));
+    }
+
+    #[tokio::test]
+    async fn test_preview_feed() {
+        init_tracing(true).unwrap();
+        // the second item has neither guid nor link, so it is skipped
+        let items: String = (0..12)
+            .map(|i| match i {
+                1 => "<item><title>No link</title></item>".to_string(),
+                i => format!("<item><link>http://localhost/{}</link></item>", i),
+            })
+            .collect();
+        let body = format!(
+            "<rss version=\"2.0\"><channel><title>Preview</title>{}</channel></rss>",
+            items
+        );
+        let url = serve_feed(body);
+
+        let response = preview_feed(test_config(), url.clone()).await.unwrap();
+        match response {
+            Response::FeedPreview(preview) => {
+                assert_eq!(preview.feed.url, url);
+                assert_eq!(preview.feed.title.as_deref(), Some("Preview"));
+                assert_eq!(preview.entries.len(), PREVIEW_ENTRIES);
+                assert_eq!(preview.total_entries, 11);
+                assert_eq!(preview.errors.len(), 1);
+                assert_eq!(preview.errors[0].index, 1);
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_feed_page() {
+        init_tracing(true).unwrap();
+        let store = Arc::new(MemoryStorage::default());
+        let page = serve_feed(
+            r#"<html><head>
+            <link rel="alternate" type="application/rss+xml" title="Posts" href="/posts.rss">
+            </head></html>"#,
+        );
+        let response = add_feed(store.clone(), test_config(), page.clone())
+            .await
+            .unwrap();
+        match response {
+            Response::FeedCandidates(candidates) => {
+                assert_eq!(candidates.len(), 1);
+                assert_eq!(candidates[0].url, page.replace("/feed.rss", "/posts.rss"));
+                assert_eq!(candidates[0].title.as_deref(), Some("Posts"));
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
+        assert!(store.all_feeds().await.unwrap().is_empty());
+
+        let page = serve_feed("<!DOCTYPE html><html><head></head></html>");
+        let e = add_feed(store, test_config(), page).await.unwrap_err();
+        assert_eq!(
+            e.downcast::<ErrorResponse>().unwrap().code,
+            ErrorCode::ParseFailed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_feed_not_public() {
+        init_tracing(true).unwrap();
+        let store = Arc::new(MemoryStorage::default());
+        let config = Arc::new(Config::default());
+        for url in [
+            serve_feed(TEST_RSS),
+            "http://169.254.169.254/latest/meta-data/".to_string(),
+            "file:///etc/passwd".to_string(),
+        ] {
+            let e = add_feed(store.clone(), config.clone(), url)
+                .await
+                .unwrap_err();
+            let e = e.downcast::<ErrorResponse>().unwrap();
+            assert_eq!(e.code, ErrorCode::BadRequest);
+        }
+        assert!(store.all_feeds().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_atom_feed() {
+        init_tracing(true).unwrap();
+        let data = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Example Feed</title>
+  <subtitle>A subtitle.</subtitle>
+  <link href="http://example.org/"/>
+  <updated>2003-12-13T18:30:02Z</updated>
+  <id>urn:uuid:60a76c80-d399-11d9-b93C-0003939e0af6</id>
+  <entry>
+    <title>Atom-Powered Robots Run Amok</title>
+    <link rel="edit" href="http://example.org/2003/12/13/atom03/edit"/>
+    <link rel="alternate" href="http://example.org/2003/12/13/atom03"/>
+    <id>urn:uuid:1225c695-cfb8-4ebb-aaaa-80da344efa6a</id>
+    <updated>2003-12-13T18:30:02Z</updated>
+    <published>2003-12-13T08:29:29-04:00</published>
+    <summary>Some text.</summary>
+    <content type="html">&lt;p&gt;Some content&lt;/p&gt;</content>
+  </entry>
+</feed>"#;
+        let mut feed = test_feed();
+        let entries = parse_test_feed(&mut feed, data, Some("application/atom+xml")).entries;
+        assert_eq!(feed.title.as_deref(), Some("Example Feed"));
+        assert_eq!(feed.description.as_deref(), Some("A subtitle."));
+        assert_eq!(feed.ext_last_update, Some(1071340202000));
+
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(
+            entry.link.as_deref(),
+            Some("http://example.org/2003/12/13/atom03")
+        );
+        assert_eq!(entry.published, 1071318569);
+        assert_eq!(entry.description.as_deref(), Some("Some text."));
+        assert_eq!(entry.content.as_deref(), Some("<p>Some content</p>"));
+    }
+
+    #[test]
+    fn test_parse_json_feed() {
+        init_tracing(true).unwrap();
+        let data = r#"{
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": "My Example Feed",
+            "home_page_url": "https://example.org/",
+            "feed_url": "https://example.org/feed.json",
+            "items": [
+                {
+                    "id": "2",
+                    "content_text": "This is a second item.",
+                    "url": "https://example.org/second-item",
+                    "date_published": "2010-02-07T14:04:00-05:00"
+                },
+                {
+                    "id": 1,
+                    "content_html": "<p>Hello, world!</p>",
+                    "url": "https://example.org/initial-post",
+                    "date_modified": "2010-02-07T14:04:00Z"
+                }
+            ]
+        }"#;
+        assert_eq!(
+            FeedFormat::detect(Some("text/plain"), data.as_bytes()),
+            Some(FeedFormat::Json)
+        );
+
+        let mut feed = test_feed();
+        let entries = parse_test_feed(&mut feed, data, None).entries;
+        assert_eq!(feed.title.as_deref(), Some("My Example Feed"));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].published, 1265569440);
+        assert_eq!(
+            entries[0].content.as_deref(),
+            Some("This is a second item.")
+        );
+        assert_eq!(entries[1].published, 1265551440);
+        assert_eq!(entries[1].content.as_deref(), Some("<p>Hello, world!</p>"));
+
+        // version 1 under http, the items without ids are known by their urls,
+        // and the bad ones are skipped alone
+        let data = r#"{
+            "version": "http://jsonfeed.org/version/1",
+            "title": "Old feed",
+            "items": [
+                { "url": "https://example.org/no-id" },
+                { "id": { "bad": true }, "url": "https://example.org/bad-id" },
+                { "title": "Neither id nor url" },
+                { "id": "4", "tags": "not a list" }
+            ]
+        }"#;
+        let parsed = parse_test_feed(&mut feed, data, None);
+        assert_eq!(feed.title.as_deref(), Some("Old feed"));
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(
+            parsed.entries[0].link.as_deref(),
+            Some("https://example.org/no-id")
+        );
+        let indexes: Vec<_> = parsed.errors.iter().map(|e| e.index).collect();
+        assert_eq!(indexes, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_parse_rdf_feed() {
+        init_tracing(true).unwrap();
+        let data = r#"<?xml version="1.0" encoding="utf-8"?>
+<rdf:RDF
+  xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+  xmlns:dc="http://purl.org/dc/elements/1.1/"
+  xmlns="http://purl.org/rss/1.0/">
+  <channel rdf:about="http://example.org/rss.rdf">
+    <title>Example RDF</title>
+    <link>http://example.org/</link>
+    <description>RSS 1.0 example</description>
+    <dc:date>2004-01-02T10:00+01:00</dc:date>
+    <items>
+      <rdf:Seq>
+        <rdf:li resource="http://example.org/1"/>
+      </rdf:Seq>
+    </items>
+  </channel>
+  <item rdf:about="http://example.org/1">
+    <title>First &amp; only</title>
+    <link>http://example.org/1</link>
+    <description><![CDATA[<p>text</p>]]></description>
+    <dc:date>2004-01-01</dc:date>
+    <dc:creator>Someone</dc:creator>
+  </item>
+</rdf:RDF>"#;
+        assert_eq!(
+            FeedFormat::detect(Some("text/xml"), data.as_bytes()),
+            Some(FeedFormat::Rdf)
+        );
+
+        let mut feed = test_feed();
+        let entries = parse_test_feed(&mut feed, data, Some("application/rdf+xml")).entries;
+        assert_eq!(feed.title.as_deref(), Some("Example RDF"));
+        assert_eq!(feed.ext_last_update, Some(1073034000000));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].title.as_deref(), Some("First & only"));
+        assert_eq!(entries[0].link.as_deref(), Some("http://example.org/1"));
+        assert_eq!(entries[0].description.as_deref(), Some("<p>text</p>"));
+        assert_eq!(entries[0].published, 1072915200);
+
+        // a bad date of the channel is ignored, the items are still read
+        let data = data.replace("2004-01-02T10:00+01:00", "last friday");
+        let entries = parse_test_feed(&mut feed, data, Some("application/rdf+xml")).entries;
+        assert_eq!(feed.ext_last_update, None);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].published, 1072915200);
+    }
+
+    #[test]
+    fn test_parse_rss_dates() {
+        init_tracing(true).unwrap();
+        let data = r#"<?xml version="1.0"?>
+<rss version="2.0" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:atom="http://www.w3.org/2005/Atom">
+  <channel>
+    <title>Dates</title>
+    <link>http://example.org/</link>
+    <description>Various dates</description>
+    <item><guid>1</guid><pubDate>Mon, 10 Jun 2003 04:00:00 GMT</pubDate></item>
+    <item><guid>2</guid><pubDate>10 juin 2003 04:00:00 +0000</pubDate></item>
+    <item><guid>3</guid><dc:date>2003-06-10T04:00:00Z</dc:date></item>
+    <item><guid>4</guid><atom:updated>2003-06-10T04:00:00Z</atom:updated></item>
+    <item><guid>5</guid><pubDate>10 juin 03 04:00 +0000</pubDate></item>
+    <item><guid>6</guid><pubDate>not a date</pubDate></item>
+  </channel>
+</rss>"#;
+        let mut feed = test_feed();
+        let entries = parse_test_feed(&mut feed, data, None).entries;
+        let sources = entries
+            .iter()
+            .map(|entry| entry.published_source)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            vec![
+                PublishedSource::PubDate,
+                PublishedSource::PubDate,
+                PublishedSource::DcDate,
+                PublishedSource::Updated,
+                PublishedSource::PubDate,
+                PublishedSource::FirstSeen,
+            ]
+        );
+        for entry in &entries[..5] {
+            assert_eq!(entry.published, 1055217600);
+        }
+    }
+
+    #[test]
+    fn test_parse_attachments() {
+        let data = r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd" xmlns:media="http://search.yahoo.com/mrss/">
+  <channel>
+    <title>Podcast</title>
+    <link>http://example.org/</link>
+    <description>Episodes</description>
+    <item>
+      <guid>episode-1</guid>
+      <title>Episode 1</title>
+      <enclosure url="http://example.org/1.mp3" length="24986239" type="audio/mpeg"/>
+      <media:content url="http://example.org/1.mp3" type="audio/mpeg"></media:content>
+      <media:content url="http://example.org/1.mp4" type="video/mp4" fileSize="1000" duration="95">
+        <media:title>Video version</media:title>
+      </media:content>
+      <itunes:duration>01:01:35</itunes:duration>
+      <itunes:episode>1</itunes:episode>
+      <itunes:image href="http://example.org/1.jpg"/>
+    </item>
+  </channel>
+</rss>"#;
+        let mut feed = test_feed();
+        let entries = parse_test_feed(&mut feed, data, None).entries;
+        let entry = &entries[0];
+        assert_eq!(
+            entry.attachments,
+            vec![
+                Attachment {
+                    url: "http://example.org/1.mp3".to_string(),
+                    mime_type: Some("audio/mpeg".to_string()),
+                    length: Some(24986239),
+                    title: None,
+                    duration: Some(3695),
+                },
+                Attachment {
+                    url: "http://example.org/1.mp4".to_string(),
+                    mime_type: Some("video/mp4".to_string()),
+                    length: Some(1000),
+                    title: Some("Video version".to_string()),
+                    duration: Some(95),
+                },
+            ]
+        );
+        assert_eq!(
+            entry.podcast,
+            Some(PodcastInfo {
+                duration: Some(3695),
+                episode: Some("1".to_string()),
+                image: Some("http://example.org/1.jpg".to_string()),
+            })
+        );
+
+        let data = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Atom podcast</title>
+  <id>urn:example</id>
+  <updated>2003-12-13T18:30:02Z</updated>
+  <entry>
+    <title>Episode</title>
+    <id>urn:example:1</id>
+    <updated>2003-12-13T18:30:02Z</updated>
+    <link rel="alternate" href="http://example.org/1"/>
+    <link rel="enclosure" href="http://example.org/1.ogg" type="audio/ogg" length="1337"/>
+  </entry>
+</feed>"#;
+        let entries = parse_test_feed(&mut feed, data, None).entries;
+        assert_eq!(entries[0].link.as_deref(), Some("http://example.org/1"));
+        assert_eq!(
+            entries[0].attachments,
+            vec![Attachment {
+                url: "http://example.org/1.ogg".to_string(),
+                mime_type: Some("audio/ogg".to_string()),
+                length: Some(1337),
+                title: None,
+                duration: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_authors_and_categories() {
+        let data = r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <channel>
+    <title>Vendor blog</title>
+    <link>http://example.org/</link>
+    <description>News</description>
+    <category>Technology</category>
+    <dc:creator>Vendor Inc.</dc:creator>
+    <item>
+      <guid>1</guid>
+      <author>jane@example.org (Jane Doe)</author>
+      <dc:creator>Jane Doe</dc:creator>
+      <dc:creator>John Roe</dc:creator>
+      <category>security</category>
+      <category domain="http://example.org/tags">releases</category>
+      <dc:subject>security</dc:subject>
+    </item>
+  </channel>
+</rss>"#;
+        let mut feed = test_feed();
+        let entries = parse_test_feed(&mut feed, data, None).entries;
+        assert_eq!(feed.authors, vec!["Vendor Inc.".to_string()]);
+        assert_eq!(feed.categories, vec!["Technology".to_string()]);
+        assert_eq!(
+            entries[0].authors,
+            vec!["Jane Doe".to_string(), "John Roe".to_string()]
+        );
+        assert_eq!(
+            entries[0].categories,
+            vec!["security".to_string(), "releases".to_string()]
+        );
+
+        let data = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Atom blog</title>
+  <id>urn:example</id>
+  <updated>2003-12-13T18:30:02Z</updated>
+  <author><name>Blog Team</name></author>
+  <entry>
+    <title>Post</title>
+    <id>urn:example:1</id>
+    <updated>2003-12-13T18:30:02Z</updated>
+    <author><name>Jane Doe</name><email>jane@example.org</email></author>
+    <category term="security" label="Security"/>
+  </entry>
+</feed>"#;
+        let entries = parse_test_feed(&mut feed, data, None).entries;
+        assert_eq!(feed.authors, vec!["Blog Team".to_string()]);
+        assert_eq!(entries[0].authors, vec!["Jane Doe".to_string()]);
+        assert_eq!(entries[0].categories, vec!["security".to_string()]);
+    }
+
+    #[test]
+    fn test_entry_ids_are_scoped() {
+        let data = r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0">
+  <channel>
+    <title>Generic guids</title>
+    <link>http://example.org/</link>
+    <description>Feeds reusing guids</description>
+    <item><guid>1</guid><link>http://example.org/1</link></item>
+  </channel>
+</rss>"#;
+        let mut ids = Vec::new();
+        for feed_id in [FeedID(1), FeedID(2)] {
+            let mut feed = StoredFeed {
+                feed_id,
+                url: format!("http://example.org/{}", feed_id.0),
+                ..Default::default()
+            };
+            let entries = parse_test_feed(&mut feed, data, None).entries;
+            assert_eq!(entries[0].key_version, ENTRY_KEY_VERSION);
+            ids.push(entries[0].entry_id.clone());
+        }
+        assert_ne!(ids[0], ids[1]);
+        // entries stored before the scoping are migrated to the same ids
+        assert_eq!(
+            ids[0],
+            EntryID::scoped(FeedID(1), EntryID(hash("1".as_bytes())))
+        );
+    }
+
+    #[test]
+    fn test_refresh_hints() {
+        use chrono::TimeZone;
+
+        let data = r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0" xmlns:sy="http://purl.org/rss/1.0/modules/syndication/">
+  <channel>
+    <title>Hints</title>
+    <link>http://example.org/</link>
+    <description>Refresh hints</description>
+    <lastBuildDate>Tue, 10 Jun 2003 09:41:01 GMT</lastBuildDate>
+    <ttl>60</ttl>
+    <sy:updatePeriod>daily</sy:updatePeriod>
+    <sy:updateFrequency>12</sy:updateFrequency>
+    <skipHours><hour>0</hour><hour>1</hour><hour>2</hour></skipHours>
+    <skipDays><day>Sunday</day></skipDays>
+  </channel>
+</rss>"#;
+        let mut feed = test_feed();
+        parse_test_feed(&mut feed, data, None);
+        assert_eq!(feed.ext_last_update, Some(1055238061000));
+        assert_eq!(feed.ttl, Some(60));
+        assert_eq!(feed.update_interval, Some(2 * 60 * 60 * 1000));
+        assert_eq!(feed.skip_hours, vec![0, 1, 2]);
+        assert_eq!(feed.skip_days, vec![6]);
+
+        // never fetched feeds are always due
+        let now = chrono::Utc
+            .with_ymd_and_hms(2022, 10, 14, 23, 30, 0)
+            .unwrap();
+        assert!(feed.is_due(now));
+
+        // friday 23:30 + 2 hours falls into the skipped hours, and saturday is not skipped
+        feed.schedule_next_fetch(now);
+        let expected = chrono::Utc.with_ymd_and_hms(2022, 10, 15, 3, 0, 0).unwrap();
+        assert_eq!(feed.next_fetch, Some(expected.timestamp_millis() as u64));
+        assert!(!feed.is_due(now));
+        assert!(feed.is_due(expected));
+
+        // saturday 23:30 + 2 hours is sunday, which is skipped as a whole, then monday night
+        feed.schedule_next_fetch(now + chrono::Duration::days(1));
+        let expected = chrono::Utc.with_ymd_and_hms(2022, 10, 17, 3, 0, 0).unwrap();
+        assert_eq!(feed.next_fetch, Some(expected.timestamp_millis() as u64));
+
+        // the user's interval wins over the hints, even when it is shorter
+        feed.poll_interval_override = Some(30 * 60 * 1000);
+        let monday = chrono::Utc
+            .with_ymd_and_hms(2022, 10, 17, 12, 0, 0)
+            .unwrap();
+        feed.schedule_next_fetch(monday);
+        let expected = monday + chrono::Duration::minutes(30);
+        assert_eq!(feed.next_fetch, Some(expected.timestamp_millis() as u64));
+    }
+
+    #[test]
+    fn test_detect_feed_format() {
+        let rss = b"\xef\xbb\xbf<?xml version=\"1.0\"?>\n<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\"><channel></channel></rss>";
+        assert_eq!(FeedFormat::detect(None, rss), Some(FeedFormat::Rss));
+
+        let atom = b"<?xml version=\"1.0\"?><feed xmlns=\"http://www.w3.org/2005/Atom\"></feed>";
+        assert_eq!(
+            FeedFormat::detect(Some("text/xml"), atom),
+            Some(FeedFormat::Atom)
+        );
+
+        assert_eq!(
+            FeedFormat::detect(Some("application/feed+json"), b"garbage"),
+            Some(FeedFormat::Json)
+        );
+        assert_eq!(
+            FeedFormat::detect(Some("text/html"), b"<html></html>"),
+            None
+        );
     }
 
     #[tokio::test]
     async fn test_refresh_feeds() {
         init_tracing(true).unwrap();
+        let store = Arc::new(MemoryStorage::default());
+        let feed = StoredFeed {
+            feed_id: FeedID(1),
+            url: serve_feed(TEST_RSS),
+            ..Default::default()
+        };
+        store.put_feed(&feed).await.unwrap();
+        // nothing listens on the port anymore
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let failing = StoredFeed {
+            feed_id: FeedID(2),
+            url: format!("http://{}/feed.rss", closed.local_addr().unwrap()),
+            ..Default::default()
+        };
+        drop(closed);
+        store.put_feed(&failing).await.unwrap();
+        let not_due = StoredFeed {
+            feed_id: FeedID(3),
+            url: "http://localhost/later.rss".to_string(),
+            next_fetch: Some(u64::MAX),
+            ..Default::default()
+        };
+        store.put_feed(&not_due).await.unwrap();
+
+        let event = RefreshFeeds { force: false };
+        let response = refresh_feeds(store.clone(), test_config(), event)
+            .await
+            .unwrap();
+        debug!(?response, "refresh_feeds");
+        let report = match response {
+            Response::RefreshReport(report) => report,
+            response => panic!("unexpected response {:?}", response),
+        };
+        let outcome = |feed_id| {
+            report
+                .feeds
+                .iter()
+                .find(|report| report.feed_id == FeedID(feed_id))
+                .unwrap()
+        };
+        assert_eq!(report.feeds.len(), 3);
+        assert_eq!(outcome(1).outcome, RefreshOutcome::Fetched);
+        assert_eq!(outcome(1).title, "Local feed");
+        assert_eq!(outcome(1).new_entries, 1);
+        assert!(matches!(
+            &outcome(2).outcome,
+            RefreshOutcome::Failed(FeedError {
+                kind: FeedErrorKind::Fetch,
+                ..
+            })
+        ));
+        assert_eq!(outcome(3).outcome, RefreshOutcome::Skipped);
+        let summary = report.summary();
+        assert!(summary.contains("1 new in Local feed"));
+        assert!(summary.contains(&format!("{} failed: ", failing.url)));
+        let (entries, _) = store.entries(&AllEntries::default(), 10).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].title.as_deref(), Some("First"));
+        let entry = entries[0].clone();
+        store
+            .set_read_ts(entry.entry_id.clone(), entry.published, 1000)
+            .await
+            .unwrap();
+
+        // the entry has been edited since, and an item without a link is added
+        let edited = TEST_RSS
+            .replace("<title>First</title>", "<title>First, edited</title>")
+            .replace("</item>", "</item><item><title>No link</title></item>");
+        let feed = StoredFeed {
+            url: serve_feed(edited),
+            ..store.feed(FeedID(1)).await.unwrap().unwrap()
+        };
+        let report = feed_worker(store.clone(), test_config(), feed)
+            .await
+            .unwrap();
+        assert_eq!(report.outcome, RefreshOutcome::Fetched);
+        assert_eq!((report.new_entries, report.updated_entries), (0, 1));
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0].index, 1);
+        let (entries, _) = store.entries(&AllEntries::default(), 10).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].title.as_deref(), Some("First, edited"));
+        // the read state is kept
+        assert_eq!(entries[0].read_ts, 1000);
+    }
+
+    #[tokio::test]
+    async fn test_feed_health() {
+        init_tracing(true).unwrap();
+        let store = Arc::new(MemoryStorage::default());
+        let config = test_config();
+        let feed = StoredFeed {
+            feed_id: FeedID(1),
+            // detected as a JSON feed by the brace, but it is not one
+            url: serve_feed("{ not a feed"),
+            ..Default::default()
+        };
+
+        let start = chrono::Utc::now();
+        feed_worker(store.clone(), config.clone(), feed.clone())
+            .await
+            .unwrap();
+        let failed = store.feed(FeedID(1)).await.unwrap().unwrap();
+        assert_eq!(failed.failures, 1);
+        assert_eq!(failed.last_status, Some(200));
+        assert_eq!(failed.last_success, None);
+        assert_eq!(
+            failed.last_error.as_ref().unwrap().kind,
+            FeedErrorKind::Parse
+        );
+        // the validators are not kept, so the feed is parsed again
+        assert_eq!(failed.body_hash, None);
+        assert_eq!(failed.health(), FeedHealth::Failing);
+
+        // nothing listens on the port anymore
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/feed.rss", closed.local_addr().unwrap());
+        drop(closed);
+        let failed = StoredFeed { url, ..failed };
+        feed_worker(store.clone(), config.clone(), failed)
+            .await
+            .unwrap();
+        let failed = store.feed(FeedID(1)).await.unwrap().unwrap();
+        assert_eq!(failed.failures, 2);
+        assert_eq!(failed.last_status, None);
+        assert_eq!(
+            failed.last_error.as_ref().unwrap().kind,
+            FeedErrorKind::Fetch
+        );
+        // the second failure in a row waits twice the first retry interval
+        let backoff = (start + chrono::Duration::minutes(30)).timestamp_millis() as u64;
+        assert!(failed.next_fetch.unwrap() >= backoff);
+
+        let recovered = StoredFeed {
+            url: serve_feed(TEST_RSS),
+            ..failed
+        };
+        feed_worker(store.clone(), config, recovered).await.unwrap();
+        let recovered = store.feed(FeedID(1)).await.unwrap().unwrap();
+        assert_eq!(recovered.failures, 0);
+        assert_eq!(recovered.last_status, Some(200));
+        assert!(recovered.last_success.is_some());
+        // the last error is kept for a while
+        assert!(recovered.last_error.is_some());
+        assert_eq!(recovered.health(), FeedHealth::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_poll_interval() {
+        init_tracing(true).unwrap();
+        let store = Arc::new(MemoryStorage::default());
+        let config = test_config();
+        let feed = StoredFeed {
+            feed_id: FeedID(1),
+            url: serve_feed(TEST_RSS),
+            ..Default::default()
+        };
+        store.put_feed(&feed).await.unwrap();
+
+        // a single entry tells nothing, so the feed is fetched as rarely as allowed
+        let start = chrono::Utc::now().timestamp_millis() as u64;
+        feed_worker(store.clone(), config.clone(), feed)
+            .await
+            .unwrap();
+        let feed = store.feed(FeedID(1)).await.unwrap().unwrap();
+        let max_interval = config.schedule.max_interval;
+        assert_eq!(feed.poll_interval, Some(max_interval));
+        assert!(feed.next_fetch.unwrap() >= start + max_interval);
+
+        // the override is kept within the bounds and makes the feed due right away
+        let update = UpdateFeed {
+            feed_id: FeedID(1),
+            poll_interval: Some(60 * 1000),
+            ..Default::default()
+        };
+        update_feed(store.clone(), config.clone(), update)
+            .await
+            .unwrap();
+        let feed = store.feed(FeedID(1)).await.unwrap().unwrap();
+        let min_interval = config.schedule.min_interval;
+        assert_eq!(feed.poll_interval_override, Some(min_interval));
+        assert!(feed.is_due(chrono::Utc::now()));
+        feed_worker(store.clone(), config.clone(), feed)
+            .await
+            .unwrap();
+        let feed = store.feed(FeedID(1)).await.unwrap().unwrap();
+        assert!(feed.next_fetch.unwrap() < start + max_interval);
+
+        let update = UpdateFeed {
+            feed_id: FeedID(1),
+            poll_interval: Some(0),
+            ..Default::default()
+        };
+        update_feed(store.clone(), config.clone(), update)
+            .await
+            .unwrap();
+        let feed = store.feed(FeedID(1)).await.unwrap().unwrap();
+        assert_eq!(feed.poll_interval_override, None);
+
+        // the feed is not modified, but the entries stored came daily until yesterday;
+        // a minute later than that, since the dates of the items are rounded to seconds
+        let day = 24 * 60 * 60 * 1000;
+        let now = chrono::Utc::now() + chrono::Duration::minutes(1);
+        let items: String = (1..=HISTORY as i64)
+            .map(|i| {
+                let published = now - chrono::Duration::days(i);
+                format!(
+                    "<item><guid>{}</guid><pubDate>{}</pubDate></item>",
+                    i,
+                    published.to_rfc2822()
+                )
+            })
+            .collect();
+        let data = format!(
+            "<rss version=\"2.0\"><channel><title>Daily</title>{}</channel></rss>",
+            items
+        );
+        let entries = parse_test_feed(&mut feed.clone(), data, None).entries;
+        put_entries(store.clone(), entries).await.unwrap();
+        let report = feed_worker(store.clone(), config, feed).await.unwrap();
+        assert_eq!(report.outcome, RefreshOutcome::NotModified);
+        let feed = store.feed(FeedID(1)).await.unwrap().unwrap();
+        assert_eq!(feed.poll_interval, Some(day / 4));
+    }
+
+    #[tokio::test]
+    async fn test_update_and_remove_feed() {
+        init_tracing(true).unwrap();
+        let store = Arc::new(MemoryStorage::default());
+        let feed = StoredFeed {
+            feed_id: FeedID(1),
+            url: "http://localhost/old.rss".to_string(),
+            etag: Some("\"v1\"".to_string()),
+            ..Default::default()
+        };
+        store.put_feed(&feed).await.unwrap();
+
+        let url = serve_feed(TEST_RSS);
+        let update = UpdateFeed {
+            feed_id: FeedID(1),
+            url: Some(url.clone()),
+            title_override: Some(" Mine ".to_string()),
+            paused: Some(true),
+            ..Default::default()
+        };
+        update_feed(store.clone(), test_config(), update)
+            .await
+            .unwrap();
+        let updated = store.feed(FeedID(1)).await.unwrap().unwrap();
+        assert_eq!(updated.url, url);
+        assert_eq!(updated.etag, None);
+        assert_eq!(updated.display_title(), Some("Mine"));
+        assert!(updated.paused);
+
+        // paused feeds are not refreshed even when forced
         let event = RefreshFeeds { force: true };
-        let response = refresh_feeds(event).await.unwrap();
-        debug!(?response, "all_feeds");
+        refresh_feeds(store.clone(), test_config(), event)
+            .await
+            .unwrap();
+        let (entries, _) = store.entries(&AllEntries::default(), 10).await.unwrap();
+        assert!(entries.is_empty());
+
+        let update = UpdateFeed {
+            feed_id: FeedID(1),
+            title_override: Some(String::new()),
+            paused: Some(false),
+            ..Default::default()
+        };
+        update_feed(store.clone(), test_config(), update)
+            .await
+            .unwrap();
+        let event = RefreshFeeds { force: true };
+        refresh_feeds(store.clone(), test_config(), event)
+            .await
+            .unwrap();
+        let updated = store.feed(FeedID(1)).await.unwrap().unwrap();
+        assert_eq!(updated.display_title(), Some("Local feed"));
+        let (entries, _) = store.entries(&AllEntries::default(), 10).await.unwrap();
+        assert_eq!(entries.len(), 1);
+
+        let update = UpdateFeed {
+            feed_id: FeedID(1),
+            url: Some("ftp://localhost/feed.rss".to_string()),
+            ..Default::default()
+        };
+        assert!(update_feed(store.clone(), test_config(), update)
+            .await
+            .is_err());
+
+        let request = RemoveFeed {
+            feed_id: FeedID(1),
+            remove_entries: true,
+        };
+        remove_feed(store.clone(), request).await.unwrap();
+        assert!(store.all_feeds().await.unwrap().is_empty());
+        let (entries, _) = store.entries(&AllEntries::default(), 10).await.unwrap();
+        assert!(entries.is_empty());
+
+        let request = RemoveFeed {
+            feed_id: FeedID(1),
+            remove_entries: false,
+        };
+        let e = remove_feed(store, request).await.unwrap_err();
+        assert_eq!(
+            e.downcast::<ErrorResponse>().unwrap().code,
+            ErrorCode::BadRequest
+        );
     }
 }
@@ -0,0 +1,619 @@
+//! fetching feeds from their servers
+
+use std::future::Future;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Error};
+use tracing::debug;
+
+use hyper::body::{Buf, Bytes, HttpBody};
+use hyper::client::connect::dns::{GaiResolver, Name};
+use hyper::client::{Client as HttpClient, HttpConnector};
+use hyper::header::{self, HeaderName};
+use hyper::service::Service;
+use hyper::{Body, Request, StatusCode};
+use hyper_rustls::HttpsConnectorBuilder;
+use url::{Host, Url};
+
+use model::events::{ErrorCode, ErrorResponse};
+use model::feed::StoredFeed;
+use model::util::hash;
+
+/// limits and the identity used when fetching feeds
+#[derive(Debug, Clone)]
+pub struct FetchOptions {
+    pub user_agent: String,
+    pub max_redirects: usize,
+    pub connect_timeout: Duration,
+    // the whole fetch including redirects and reading the body
+    pub timeout: Duration,
+    // bytes, checked both before and after decompression
+    pub max_body_size: usize,
+    // hosts which may be fetched even though they are not public, e.g. a feed on the local network
+    pub allowed_hosts: Vec<String>,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        FetchOptions {
+            user_agent: concat!("feederito/", env!("CARGO_PKG_VERSION")).to_string(),
+            max_redirects: 5,
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+            max_body_size: 10 * 1024 * 1024,
+            allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+/// raw feed data as it was received from the feed's server
+#[derive(Debug, Clone, Default)]
+pub struct FetchedFeed {
+    // where the feed was fetched from in the end, after the redirects
+    pub url: String,
+    pub content_type: Option<String>,
+    pub body: Bytes,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    // the new location of the feed, if it was only reached through permanent redirects
+    pub moved_to: Option<String>,
+}
+
+impl FetchedFeed {
+    pub fn body_hash(&self) -> u64 {
+        hash(&self.body)
+    }
+
+    /// keeps what is needed to make the next fetch of the feed conditional
+    pub fn remember_validators(&self, feed: &mut StoredFeed) {
+        feed.etag = self.etag.clone();
+        feed.last_modified = self.last_modified.clone();
+        feed.body_hash = Some(self.body_hash());
+    }
+}
+
+/// the server has answered with a status the feed cannot be read from
+#[derive(Debug)]
+pub struct StatusError(pub StatusCode);
+
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unexpected response status {}", self.0)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// fetches the feed, making the request conditional if there are validators of the last fetch;
+/// returns None when the server says the feed is not modified since then
+pub async fn fetch_feed(
+    url: &str,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+    options: &FetchOptions,
+) -> Result<Option<FetchedFeed>, Error> {
+    tokio::time::timeout(options.timeout, fetch(url, etag, last_modified, options))
+        .await
+        .map_err(|_| anyhow!("fetching took longer than {:?}", options.timeout))?
+}
+
+async fn fetch(
+    url: &str,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+    options: &FetchOptions,
+) -> Result<Option<FetchedFeed>, Error> {
+    let resolver = PublicResolver {
+        inner: GaiResolver::new(),
+        allowed_hosts: Arc::new(options.allowed_hosts.clone()),
+    };
+    let mut http = HttpConnector::new_with_resolver(resolver);
+    http.set_connect_timeout(Some(options.connect_timeout));
+    http.enforce_http(false);
+    let https = HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(http);
+    let client: HttpClient<_, Body> = HttpClient::builder().build(https);
+
+    let mut url = Url::parse(url)?;
+    let mut moved_to = None;
+    // the feed has moved only if every redirect on the way is permanent
+    let mut permanent = true;
+    for _ in 0..=options.max_redirects {
+        // redirects may lead anywhere, so every location is checked before it is requested
+        check_url(&url, options)?;
+        debug!("fetching {}", url);
+        let mut request = Request::get(url.as_str())
+            .header(header::USER_AGENT, &options.user_agent)
+            .header(header::ACCEPT_ENCODING, "gzip, deflate, br");
+        if let Some(etag) = etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+        let res = client
+            .request(request.body(Body::empty())?)
+            .await
+            .map_err(reject_not_public)?;
+        let header = |name: HeaderName| {
+            res.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+
+        let status = res.status();
+        match status {
+            StatusCode::OK => {}
+            StatusCode::NOT_MODIFIED => return Ok(None),
+            StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT => {
+                let location = header(header::LOCATION)
+                    .ok_or_else(|| anyhow!("{} response without location", status))?;
+                url = url.join(&location)?;
+                permanent &= matches!(
+                    status,
+                    StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT
+                );
+                if permanent {
+                    moved_to = Some(url.to_string());
+                }
+                debug!(%status, %url, "redirected");
+                continue;
+            }
+            status => {
+                debug!("error fetching {:?}", res);
+                return Err(StatusError(status).into());
+            }
+        }
+
+        let content_type = header(header::CONTENT_TYPE);
+        let etag = header(header::ETAG);
+        let last_modified = header(header::LAST_MODIFIED);
+        let encoding = header(header::CONTENT_ENCODING);
+        let body = read_body(res.into_body(), options.max_body_size).await?;
+        let body = decode_body(encoding.as_deref(), body, options.max_body_size)?;
+        return Ok(Some(FetchedFeed {
+            url: url.to_string(),
+            content_type,
+            body,
+            etag,
+            last_modified,
+            moved_to,
+        }));
+    }
+    bail!("more than {} redirects", options.max_redirects)
+}
+
+/// feeds are fetched on behalf of clients, so they must not be able to make the lambda
+/// request its own network, e.g. the instance metadata; only the schemes fetched are allowed
+/// and hosts given as addresses must be public, hosts given as names are checked when resolved
+fn check_url(url: &Url, options: &FetchOptions) -> Result<(), Error> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ErrorResponse::new(
+            ErrorCode::BadRequest,
+            format!("{} is not an http(s) url", url),
+        )
+        .into());
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| ErrorResponse::new(ErrorCode::BadRequest, format!("{} has no host", url)))?;
+    if is_allowed_host(&options.allowed_hosts, host) {
+        return Ok(());
+    }
+    let ip = match url.host() {
+        Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+        _ => return Ok(()),
+    };
+    if !is_public(ip) {
+        return Err(ErrorResponse::new(
+            ErrorCode::BadRequest,
+            format!("{} is not a public address", ip),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn is_allowed_host(allowed_hosts: &[String], host: &str) -> bool {
+    // IPv6 hosts are in brackets in urls, but not in the list
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    allowed_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+}
+
+/// tells whether the address is reachable over the internet, rather than
+/// a loopback, private, link-local (including the metadata service) or reserved one
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "this" network
+        || a == 0
+        // shared address space of carrier-grade NATs
+        || (a == 100 && (b & 0b1100_0000) == 64)
+        // IETF protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // benchmarking
+        || (a == 198 && (b & 0b1111_1110) == 18)
+        // reserved
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // IPv4-mapped, IPv4-compatible and NAT64 addresses lead to the IPv4 one they embed,
+    // the unspecified and loopback addresses among them are not public as IPv4 ones too
+    let embedded = Ipv4Addr::new(
+        (segments[6] >> 8) as u8,
+        segments[6] as u8,
+        (segments[7] >> 8) as u8,
+        segments[7] as u8,
+    );
+    if segments[..6] == [0, 0, 0, 0, 0, 0xffff]
+        || segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0]
+        // IPv4-compatible, deprecated, but still handled by some stacks
+        || segments[..6] == [0, 0, 0, 0, 0, 0]
+    {
+        return is_public_v4(embedded);
+    }
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local, including the metadata service at fd00:ec2::254
+        || (segments[0] & 0xfe00) == 0xfc00
+        // link-local
+        || (segments[0] & 0xffc0) == 0xfe80
+        // site-local, deprecated but may still be routed internally
+        || (segments[0] & 0xffc0) == 0xfec0
+        // documentation
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+}
+
+/// resolves host names to their public addresses only, the connection is made to the addresses
+/// checked, so a name resolving to a public address at first and to a private one later
+/// cannot get past the check
+#[derive(Clone)]
+struct PublicResolver {
+    inner: GaiResolver,
+    allowed_hosts: Arc<Vec<String>>,
+}
+
+type ResolveError = Box<dyn std::error::Error + Send + Sync>;
+
+impl Service<Name> for PublicResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = ResolveError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let allowed = is_allowed_host(&self.allowed_hosts, name.as_str());
+        let resolving = self.inner.call(name.clone());
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = resolving
+                .await?
+                .filter(|addr| allowed || is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(NotPublicError(name.to_string()).into());
+            }
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+/// the host name resolves to no public address
+#[derive(Debug)]
+struct NotPublicError(String);
+
+impl std::fmt::Display for NotPublicError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} does not resolve to a public address", self.0)
+    }
+}
+
+impl std::error::Error for NotPublicError {}
+
+/// the names resolving to no public address are rejected the same way as such addresses,
+/// rather than as a failed fetch; the error of the resolver is deep in the connection one
+fn reject_not_public(e: hyper::Error) -> Error {
+    let mut source = std::error::Error::source(&e);
+    while let Some(error) = source {
+        if let Some(not_public) = error.downcast_ref::<NotPublicError>() {
+            return ErrorResponse::new(ErrorCode::BadRequest, not_public.to_string()).into();
+        }
+        source = error.source();
+    }
+    e.into()
+}
+
+/// reads the body up to the size limit, so a huge feed is not loaded into memory as a whole
+async fn read_body(mut body: Body, max_size: usize) -> Result<Bytes, Error> {
+    if let Some(size) = body.size_hint().exact() {
+        if size as usize > max_size {
+            bail!("body of {} bytes is larger than {} bytes", size, max_size);
+        }
+    }
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if data.len() + chunk.len() > max_size {
+            bail!("body is larger than {} bytes", max_size);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(data))
+}
+
+fn decode_body(encoding: Option<&str>, body: Bytes, max_size: usize) -> Result<Bytes, Error> {
+    let encoding = encoding.map(|encoding| encoding.trim().to_lowercase());
+    let reader: Box<dyn Read> = match encoding.as_deref() {
+        None | Some("") | Some("identity") => return Ok(body),
+        Some("gzip") | Some("x-gzip") => Box::new(flate2::read::GzDecoder::new(body.reader())),
+        Some("deflate") => Box::new(flate2::read::ZlibDecoder::new(body.reader())),
+        Some("br") => Box::new(brotli::Decompressor::new(body.reader(), 4096)),
+        Some(encoding) => bail!("unsupported content encoding {}", encoding),
+    };
+    // the limit applies to the decompressed body too, it may be a compression bomb
+    let mut decoded = Vec::new();
+    reader.take(max_size as u64 + 1).read_to_end(&mut decoded)?;
+    if decoded.len() > max_size {
+        bail!("decompressed body is larger than {} bytes", max_size);
+    }
+    Ok(Bytes::from(decoded))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::init_tracing;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response as HttpResponse, Server};
+    use std::convert::Infallible;
+    use std::io::Write;
+
+    const TEST_BODY: &str = "<rss version=\"2.0\"><channel><title>Test</title></channel></rss>";
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn brotli(data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = brotli::CompressorWriter::new(&mut encoded, 4096, 5, 22);
+            encoder.write_all(data).unwrap();
+        }
+        encoded
+    }
+
+    async fn handle(request: Request<Body>) -> Result<HttpResponse<Body>, Infallible> {
+        let redirect = |status, location: &str| {
+            HttpResponse::builder()
+                .status(status)
+                .header(header::LOCATION, location)
+                .body(Body::empty())
+        };
+        let response = match request.uri().path() {
+            "/feed" => HttpResponse::builder().body(Body::from(TEST_BODY)),
+            "/moved" => redirect(StatusCode::MOVED_PERMANENTLY, "/feed"),
+            "/moved-twice" => redirect(StatusCode::PERMANENT_REDIRECT, "moved"),
+            "/found" => redirect(StatusCode::FOUND, "/moved"),
+            "/loop" => redirect(StatusCode::FOUND, "/loop"),
+            "/metadata" => redirect(
+                StatusCode::FOUND,
+                "http://169.254.169.254/latest/meta-data/",
+            ),
+            "/localhost" => {
+                // the same server, but named, so the name has to be resolved
+                let host = request.headers()[header::HOST].to_str().unwrap();
+                let port = host.rsplit(':').next().unwrap();
+                redirect(
+                    StatusCode::FOUND,
+                    &format!("http://localhost:{}/feed", port),
+                )
+            }
+            "/file" => redirect(StatusCode::FOUND, "file:///etc/passwd"),
+            "/gzip" => HttpResponse::builder()
+                .header(header::CONTENT_ENCODING, "gzip")
+                .body(Body::from(gzip(TEST_BODY.as_bytes()))),
+            "/br" => HttpResponse::builder()
+                .header(header::CONTENT_ENCODING, "br")
+                .body(Body::from(brotli(TEST_BODY.as_bytes()))),
+            "/bomb" => HttpResponse::builder()
+                .header(header::CONTENT_ENCODING, "gzip")
+                .body(Body::from(gzip(&[b' '; 1024 * 1024]))),
+            "/user-agent" => {
+                let user_agent = request.headers().get(header::USER_AGENT).cloned();
+                HttpResponse::builder().body(Body::from(
+                    user_agent
+                        .map(|ua| ua.as_bytes().to_vec())
+                        .unwrap_or_default(),
+                ))
+            }
+            "/slow" => {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                HttpResponse::builder().body(Body::from(TEST_BODY))
+            }
+            _ => HttpResponse::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty()),
+        };
+        Ok(response.unwrap())
+    }
+
+    fn serve() -> String {
+        let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    /// the test server is local, so it has to be allowed explicitly
+    fn local_options() -> FetchOptions {
+        FetchOptions {
+            allowed_hosts: vec!["127.0.0.1".to_string()],
+            ..Default::default()
+        }
+    }
+
+    async fn fetch_path(
+        base: &str,
+        path: &str,
+        options: &FetchOptions,
+    ) -> Result<FetchedFeed, Error> {
+        let fetched = fetch_feed(&format!("{}{}", base, path), None, None, options).await?;
+        Ok(fetched.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_redirects() {
+        init_tracing(true).unwrap();
+        let base = serve();
+        let options = local_options();
+
+        let fetched = fetch_path(&base, "/moved-twice", &options).await.unwrap();
+        assert_eq!(fetched.body, TEST_BODY);
+        assert_eq!(fetched.moved_to, Some(format!("{}/feed", base)));
+
+        // a temporary redirect on the way means the feed has not moved
+        let fetched = fetch_path(&base, "/found", &options).await.unwrap();
+        assert_eq!(fetched.body, TEST_BODY);
+        assert_eq!(fetched.moved_to, None);
+
+        assert!(fetch_path(&base, "/loop", &options).await.is_err());
+        let e = fetch_path(&base, "/missing", &options).await.unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<StatusError>().map(|e| e.0),
+            Some(StatusCode::NOT_FOUND)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_compressed() {
+        init_tracing(true).unwrap();
+        let base = serve();
+        let options = local_options();
+
+        for path in ["/gzip", "/br"] {
+            let fetched = fetch_path(&base, path, &options).await.unwrap();
+            assert_eq!(fetched.body, TEST_BODY);
+        }
+
+        let options = FetchOptions {
+            max_body_size: 64 * 1024,
+            ..local_options()
+        };
+        assert!(fetch_path(&base, "/bomb", &options).await.is_err());
+        let options = FetchOptions {
+            max_body_size: 16,
+            ..local_options()
+        };
+        assert!(fetch_path(&base, "/feed", &options).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_user_agent_and_timeout() {
+        init_tracing(true).unwrap();
+        let base = serve();
+        let options = FetchOptions {
+            user_agent: "test agent".to_string(),
+            timeout: Duration::from_millis(500),
+            ..local_options()
+        };
+
+        let fetched = fetch_path(&base, "/user-agent", &options).await.unwrap();
+        assert_eq!(fetched.body, "test agent");
+        assert!(fetch_path(&base, "/slow", &options).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_not_public() {
+        init_tracing(true).unwrap();
+        let base = serve();
+
+        // not allowed unless listed
+        let options = FetchOptions::default();
+        assert!(fetch_path(&base, "/feed", &options).await.is_err());
+
+        let options = local_options();
+        for path in ["/metadata", "/localhost", "/file"] {
+            let e = fetch_path(&base, path, &options).await.unwrap_err();
+            debug!(error = ?e, path, "rejected");
+            let e = e.downcast::<ErrorResponse>().unwrap();
+            assert_eq!(e.code, ErrorCode::BadRequest);
+        }
+        let options = FetchOptions {
+            allowed_hosts: vec!["127.0.0.1".to_string(), "LocalHost".to_string()],
+            ..Default::default()
+        };
+        let fetched = fetch_path(&base, "/localhost", &options).await.unwrap();
+        assert_eq!(fetched.body, TEST_BODY);
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in [
+            "93.184.216.34",
+            "2606:2800:220:1:248:1893:25c8:1946",
+            "::ffff:1.1.1.1",
+            "::1.1.1.1",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fe80::1",
+            "fd00:ec2::254",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "::a9fe:a9fe",
+            "::7f00:1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
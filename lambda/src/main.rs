@@ -1,14 +1,23 @@
+mod config;
+mod discover;
 mod entries;
 mod feeds;
+mod fetch;
+mod schedule;
+mod storage;
 mod utils;
 
+use std::sync::Arc;
+
 use anyhow::Error;
 use lambda_runtime::{service_fn, LambdaEvent};
 
 use tracing::{debug, warn};
 
+use crate::config::Config;
 use crate::entries::*;
 use crate::feeds::*;
+use crate::storage::Storage;
 use crate::utils::*;
 use model::events::*;
 
@@ -50,7 +59,13 @@ async fn main() -> Result<(), lambda_runtime::Error> {
     eprintln!("main started");
     init_tracing(false)?;
 
-    let func = service_fn(route_lambda);
+    let config = Config::from_env();
+    debug!(?config, "loaded config");
+    let store = storage::from_env(&config).await?;
+    let config = Arc::new(config);
+    let func = service_fn(|event: LambdaEvent<WrappedEvent>| {
+        route(event.payload, store.clone(), config.clone())
+    });
 
     if std::env::var("DEBUG").unwrap_or_default().is_empty() {
         debug!("starting lambda runtime");
@@ -61,35 +76,57 @@ async fn main() -> Result<(), lambda_runtime::Error> {
     } else {
         debug!("falling down to debug");
 
-        let event = WrappedEvent::Just(Event::FixDatabase(()));
-        let result = route(event).await?;
+        // a harmless event, the migrations are run only when asked explicitly
+        let event = WrappedEvent::Just(Event::Hello(()));
+        let result = route(event, store.clone(), config.clone()).await?;
         debug!("{:?}", result);
     }
     Ok(())
 }
 
-async fn route_lambda(inevent: LambdaEvent<WrappedEvent>) -> Result<Response, Error> {
-    route(inevent.payload).await
-}
-
-async fn route(inevent: WrappedEvent) -> Result<Response, Error> {
+async fn route(
+    inevent: WrappedEvent,
+    store: Arc<dyn Storage>,
+    config: Arc<Config>,
+) -> Result<Response, Error> {
     debug!(event=?inevent, "event incoming");
     let decoded = inevent.into_event();
     debug!(event=?&decoded, "decoded to");
-    let response = match decoded {
-        Event::RefreshFeeds(ev) => refresh_feeds(ev).await?,
-        Event::AllFeeds(ev) => all_feeds(ev).await?,
-        Event::AllEntries(ev) => all_entries(ev).await?,
-        Event::MarkReadUnread(ev) => mark_read_unread(ev).await?,
-        Event::AddFeed(ev) => add_feed(ev).await?,
-        Event::FixDatabase(ev) => fix_database(ev).await?,
+    // errors are returned as a regular response, so the frontend could show them
+    let response = match handle_event(decoded, store, config).await {
+        Ok(response) => response,
+        Err(e) => {
+            warn!(error = ?e, "error handling event");
+            Response::Error(classify_error(e))
+        }
+    };
+    Ok(response)
+}
+
+async fn handle_event(
+    event: Event,
+    store: Arc<dyn Storage>,
+    config: Arc<Config>,
+) -> Result<Response, Error> {
+    let response = match event {
+        Event::Hello(ev) => hello(store, ev).await?,
+        Event::RefreshFeeds(ev) => refresh_feeds(store, config, ev).await?,
+        Event::AllFeeds(ev) => all_feeds(store, ev).await?,
+        Event::AllEntries(ev) => all_entries(store, ev).await?,
+        Event::MarkReadUnread(ev) => mark_read_unread(store, ev).await?,
+        Event::MarkAllRead(ev) => mark_all_read(store, ev).await?,
+        Event::AddFeed(ev) => add_feed(store, config, ev).await?,
+        Event::PreviewFeed(ev) => preview_feed(config, ev).await?,
+        Event::RemoveFeed(ev) => remove_feed(store, ev).await?,
+        Event::UpdateFeed(ev) => update_feed(store, config, ev).await?,
+        Event::FixDatabase(ev) => fix_database(store, ev).await?,
         Event::Bad(input, err_string) => {
             let in_string = String::from_utf8_lossy(&input);
             warn!(event = ?&in_string, error = ?&err_string, "could not deserialize input event");
-            Response::Ok(OkResponse::new(format!(
-                "WTF: {} {}",
-                in_string, err_string
-            )))
+            Response::new_error(
+                ErrorCode::BadRequest,
+                format!("could not deserialize event: {}", err_string),
+            )
         }
     };
     Ok(response)
@@ -98,6 +135,7 @@ async fn route(inevent: WrappedEvent) -> Result<Response, Error> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use model::feed::{FeedID, StoredFeed};
 
     #[test]
     fn event_deserialize_wrapped() {
@@ -108,6 +146,55 @@ mod test {
         debug!("{:?}", e.into_event());
     }
 
+    #[tokio::test]
+    async fn bad_event_response() {
+        init_tracing(true).unwrap();
+        let s = "{\"noSuchEvent\":{}}";
+        let e: WrappedEvent = serde_json::from_str(s).unwrap();
+        let store = Arc::new(storage::MemoryStorage::default());
+        match route(e, store, Arc::new(Config::default())).await.unwrap() {
+            Response::Error(e) => assert_eq!(e.code, ErrorCode::BadRequest),
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn scheduled_event_refreshes_due_feeds() {
+        init_tracing(true).unwrap();
+        let s = r#"{"version":"0","id":"53dc4d37-cffa-4f76-80c9-8b7d4a4d2eaa","detail-type":"Scheduled Event","source":"aws.events","account":"123456789012","time":"2022-04-17T09:20:00Z","region":"eu-west-1","resources":["arn:aws:events:eu-west-1:123456789012:rule/refresh-feeds"],"detail":{}}"#;
+        let e: WrappedEvent = serde_json::from_str(s).unwrap();
+        assert!(matches!(e, WrappedEvent::Scheduled(_)));
+
+        let store = Arc::new(storage::MemoryStorage::default());
+        let feed = StoredFeed {
+            feed_id: FeedID(1),
+            url: "http://localhost/feed.rss".to_string(),
+            next_fetch: Some(u64::MAX),
+            ..Default::default()
+        };
+        store.put_feed(&feed).await.unwrap();
+        // the feed is not due, so a refresh which is not forced skips it
+        match route(e, store, Arc::new(Config::default())).await.unwrap() {
+            Response::RefreshReport(report) => {
+                assert_eq!(report.feeds.len(), 1);
+                assert_eq!(report.feeds[0].outcome, RefreshOutcome::Skipped);
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
+
+        let s = r#"{"detail-type":"EC2 Instance State-change Notification","source":"aws.ec2"}"#;
+        let e: WrappedEvent = serde_json::from_str(s).unwrap();
+        assert!(matches!(e.into_event(), Event::Bad(_, _)));
+    }
+
+    #[test]
+    fn hello_deserialize() {
+        init_tracing(true).unwrap();
+        let s = "{\"hello\":null}";
+        let e: WrappedEvent = serde_json::from_str(s).unwrap();
+        assert!(matches!(e.into_event(), Event::Hello(())));
+    }
+
     #[test]
     fn event_deserialize() {
         init_tracing(true).unwrap();
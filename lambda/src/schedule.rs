@@ -0,0 +1,114 @@
+//! adaptive polling: feeds are fetched about as often as they get new entries,
+//! so the quiet ones don't eat the free tier limits
+
+use model::entry::StoredEntry;
+
+/// the latest entries the posting frequency is learned from
+pub const HISTORY: usize = 20;
+// the feed is fetched this many times per the usual time between its entries
+const POLLS_PER_ENTRY: u64 = 4;
+
+/// the bounds of the polling interval, in milliseconds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleOptions {
+    pub min_interval: u64,
+    pub max_interval: u64,
+}
+
+impl Default for ScheduleOptions {
+    fn default() -> Self {
+        ScheduleOptions {
+            min_interval: 15 * 60 * 1000,
+            max_interval: 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+impl ScheduleOptions {
+    pub fn clamp(&self, interval: u64) -> u64 {
+        interval.max(self.min_interval).min(self.max_interval)
+    }
+}
+
+/// learns the polling interval from the publish dates of the entries the feed has now;
+/// the interval grows while the feed stays quiet, and is the longest one if it has
+/// too few entries to tell anything; `now` is in milliseconds like the interval
+pub fn learn_poll_interval(entries: &[StoredEntry], now: u64, options: &ScheduleOptions) -> u64 {
+    // estimated dates are the times the entries were seen first, they tell nothing;
+    // the entries are published in seconds
+    let mut published: Vec<u64> = entries
+        .iter()
+        .filter(|entry| !entry.published_source.is_estimated())
+        .map(|entry| entry.published * 1000)
+        .collect();
+    published.sort_unstable_by(|a, b| b.cmp(a));
+    published.dedup();
+    published.truncate(HISTORY);
+
+    let (newest, oldest) = match (published.first(), published.last()) {
+        (Some(newest), Some(oldest)) if published.len() > 1 => (*newest, *oldest),
+        _ => return options.max_interval,
+    };
+    let gap = (newest - oldest) / (published.len() as u64 - 1);
+    let quiet = now.saturating_sub(newest);
+    options.clamp(gap.max(quiet) / POLLS_PER_ENTRY)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+    use model::feed::FeedID;
+
+    const MINUTE: u64 = 60 * 1000;
+    const HOUR: u64 = 60 * MINUTE;
+    const NOW: u64 = 1_650_000_000_000;
+
+    /// the entries parsed from the items published every so often before `NOW`,
+    /// or from the items without dates
+    fn entries(every: u64, count: u64, dated: bool) -> Vec<StoredEntry> {
+        (0..count)
+            .map(|i| {
+                let published = chrono::Utc.timestamp_millis_opt((NOW - i * every) as i64);
+                let item = rss::Item {
+                    link: Some(format!("http://example.org/{}", i)),
+                    pub_date: dated.then(|| published.unwrap().to_rfc2822()),
+                    ..Default::default()
+                };
+                StoredEntry::from_rss_item(FeedID(1), item).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_learn_poll_interval() {
+        let options = ScheduleOptions::default();
+        // a daily blog is fetched a few times a day
+        let daily = entries(24 * HOUR, 10, true);
+        assert_eq!(learn_poll_interval(&daily, NOW, &options), 6 * HOUR);
+        // a news wire as often as allowed
+        let wire = entries(5 * MINUTE, 50, true);
+        assert_eq!(learn_poll_interval(&wire, NOW, &options), 15 * MINUTE);
+        // the blog gone quiet for a week is fetched daily
+        let quiet = learn_poll_interval(&daily, NOW + 7 * 24 * HOUR, &options);
+        assert_eq!(quiet, options.max_interval);
+        // too few dates to learn from
+        let single = entries(HOUR, 1, true);
+        assert_eq!(
+            learn_poll_interval(&single, NOW, &options),
+            options.max_interval
+        );
+        let estimated = entries(HOUR, 10, false);
+        assert_eq!(
+            learn_poll_interval(&estimated, NOW, &options),
+            options.max_interval
+        );
+
+        let options = ScheduleOptions {
+            min_interval: HOUR,
+            max_interval: 2 * HOUR,
+        };
+        assert_eq!(learn_poll_interval(&wire, NOW, &options), HOUR);
+        assert_eq!(learn_poll_interval(&daily, NOW, &options), 2 * HOUR);
+    }
+}
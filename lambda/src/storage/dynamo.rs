@@ -0,0 +1,582 @@
+use std::collections::HashMap;
+use tokio::task::spawn;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use tracing::{debug, warn};
+
+use aws_sdk_dynamodb::{
+    model::AttributeValue, model::DeleteRequest, model::KeysAndAttributes, model::ReturnValue,
+    model::WriteRequest, types::SdkError, Client,
+};
+use serde::{Deserialize, Serialize};
+use serde_dynamo::{from_item, from_items, to_attribute_value, to_item};
+
+use model::entry::*;
+use model::events::*;
+use model::feed::*;
+
+use super::{entry_not_found, updated_entry, PutOutcome, Storage};
+use crate::config::Config;
+
+/// the position in the index to continue from, it is DynamoDB's LastEvaluatedKey
+/// consisting of the keys of both the table and the index;
+/// clients get it serialized and should not make any assumptions about its content
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EntriesCursor {
+    entry_id: EntryID,
+    published: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    read_ts: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    feed_id: Option<FeedID>,
+}
+
+impl EntriesCursor {
+    fn encode(key: HashMap<String, AttributeValue>) -> Result<String, Error> {
+        let cursor: Self = from_item(key)?;
+        Ok(serde_json::to_string(&cursor)?)
+    }
+
+    fn decode(cursor: &str) -> Result<HashMap<String, AttributeValue>, Error> {
+        let cursor: Self = serde_json::from_str(cursor).map_err(|e| {
+            ErrorResponse::new(ErrorCode::BadRequest, format!("invalid cursor: {}", e))
+        })?;
+        Ok(to_item(cursor)?)
+    }
+}
+
+pub struct DynamoStorage {
+    client: Client,
+    config: Config,
+}
+
+impl DynamoStorage {
+    pub async fn from_env(config: Config) -> Self {
+        let shared_config = aws_config::load_from_env().await;
+        DynamoStorage {
+            client: Client::new(&shared_config),
+            config,
+        }
+    }
+
+    async fn rekey_entry(&self, item: HashMap<String, AttributeValue>) -> Result<(), Error> {
+        let mut entry: StoredEntry = from_item(item)?;
+        let old_key = HashMap::from([
+            ("entryId".to_string(), to_attribute_value(&entry.entry_id)?),
+            (
+                "published".to_string(),
+                to_attribute_value(entry.published)?,
+            ),
+        ]);
+        entry.entry_id = EntryID::scoped(entry.feed_id, entry.entry_id);
+        entry.key_version = ENTRY_KEY_VERSION;
+
+        // the entry may be already stored with the new key by a refresh,
+        // its read state wins then, unless it is still unread
+        let put = self
+            .client
+            .put_item()
+            .table_name(&self.config.entries_table)
+            .set_item(Some(to_item(&entry)?))
+            .condition_expression("attribute_not_exists(entryId) OR readTs = :unread")
+            .expression_attribute_values(":unread", to_attribute_value(0)?)
+            .send()
+            .await;
+        if let Err(e) = put {
+            match e {
+                SdkError::ServiceError { ref err, .. }
+                    if err.is_conditional_check_failed_exception() =>
+                {
+                    debug!(entry_id = ?entry.entry_id, "entry is already re-keyed");
+                }
+                e => return Err(e.into()),
+            }
+        }
+
+        self.client
+            .delete_item()
+            .table_name(&self.config.entries_table)
+            .set_key(Some(old_key))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+/// gets the entries by their keys, a batch takes up to 100 of them;
+/// the keys DynamoDB has not processed are retried
+async fn batch_get_entries(
+    client: Client,
+    table: String,
+    keys: Vec<HashMap<String, AttributeValue>>,
+) -> Result<Vec<HashMap<String, AttributeValue>>, Error> {
+    let mut items = Vec::with_capacity(keys.len());
+    let keys_attrs = KeysAndAttributes::builder().set_keys(Some(keys)).build();
+    let mut requests = HashMap::from([(table.clone(), keys_attrs)]);
+    while !requests.is_empty() {
+        let output = client
+            .batch_get_item()
+            .set_request_items(Some(requests))
+            .send()
+            .await
+            .map_err(|e| {
+                warn!(error = ?e, "querying entries from main table");
+                e
+            })?;
+        if let Some(mut responses) = output.responses {
+            items.extend(responses.remove(&table).unwrap_or_default());
+        }
+        requests = output.unprocessed_keys.unwrap_or_default();
+        requests.retain(|_, keys_attrs| keys_attrs.keys().map_or(false, |keys| !keys.is_empty()));
+        if !requests.is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+    }
+    Ok(items)
+}
+
+#[async_trait]
+impl Storage for DynamoStorage {
+    async fn status(&self) -> StorageStatus {
+        // reads a single item, which the function is allowed to anyway,
+        // unlike describing the table
+        match self
+            .client
+            .scan()
+            .table_name(&self.config.feeds_table)
+            .limit(1)
+            .send()
+            .await
+        {
+            Ok(_) => StorageStatus::Ok,
+            Err(e) => {
+                warn!(error = ?e, "storage is unavailable");
+                StorageStatus::Unavailable(e.to_string())
+            }
+        }
+    }
+
+    async fn all_feeds(&self) -> Result<Vec<StoredFeed>, Error> {
+        let result = self
+            .client
+            .scan()
+            .table_name(&self.config.feeds_table)
+            .send()
+            .await?;
+        if let Some(items) = result.items {
+            Ok(from_items(items)?)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    async fn feed(&self, feed_id: FeedID) -> Result<Option<StoredFeed>, Error> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.config.feeds_table)
+            .key("feedId", to_attribute_value(feed_id)?)
+            .send()
+            .await?;
+        Ok(output.item.map(from_item).transpose()?)
+    }
+
+    async fn put_feed(&self, feed: &StoredFeed) -> Result<(), Error> {
+        let resp = self
+            .client
+            .put_item()
+            .table_name(&self.config.feeds_table)
+            .set_item(Some(to_item(feed)?))
+            .send()
+            .await
+            .map_err(|e| {
+                warn!(error = ?e, "error putting feed");
+                e
+            })?;
+        debug!(?resp, "put feed response");
+        Ok(())
+    }
+
+    async fn remove_feed(&self, feed_id: FeedID) -> Result<(), Error> {
+        self.client
+            .delete_item()
+            .table_name(&self.config.feeds_table)
+            .key("feedId", to_attribute_value(feed_id)?)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_feed_entries(&self, feed_id: FeedID) -> Result<usize, Error> {
+        // the keys of the entries are collected from the feed index first,
+        // deleting while paging through it could skip some of them
+        let mut keys = Vec::new();
+        let mut start_key = None;
+        loop {
+            let output = self
+                .client
+                .query()
+                .table_name(&self.config.entries_table)
+                .index_name(&self.config.feed_index)
+                .key_condition_expression("feedId = :feedId")
+                .expression_attribute_values(":feedId", to_attribute_value(feed_id)?)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+            for mut item in output.items.unwrap_or_default() {
+                item.retain(|k, _| k.as_str() == "entryId" || k.as_str() == "published");
+                keys.push(item);
+            }
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+
+        // a batch takes up to 25 requests, the ones DynamoDB has not processed are retried
+        for chunk in keys.chunks(25) {
+            let deletes = chunk
+                .iter()
+                .map(|key| {
+                    WriteRequest::builder()
+                        .delete_request(DeleteRequest::builder().set_key(Some(key.clone())).build())
+                        .build()
+                })
+                .collect();
+            let mut requests = HashMap::from([(self.config.entries_table.clone(), deletes)]);
+            while !requests.is_empty() {
+                let output = self
+                    .client
+                    .batch_write_item()
+                    .set_request_items(Some(requests))
+                    .send()
+                    .await?;
+                requests = output.unprocessed_items.unwrap_or_default();
+                requests.retain(|_, requests| !requests.is_empty());
+                if !requests.is_empty() {
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                }
+            }
+        }
+        Ok(keys.len())
+    }
+
+    async fn entries(
+        &self,
+        request: &AllEntries,
+        limit: u32,
+    ) -> Result<(Vec<StoredEntry>, Option<String>), Error> {
+        let client = &self.client;
+        let limit = limit as i32;
+        let start_key = request
+            .cursor
+            .as_deref()
+            .map(EntriesCursor::decode)
+            .transpose()?;
+        // published is the sort key of both indexes, so the older entries are a range of them
+        let older_than: Option<AttributeValue> =
+            request.older_than.map(to_attribute_value).transpose()?;
+        let key_condition = |hash_condition: &str| match older_than {
+            Some(_) => format!("{} AND published < :older", hash_condition),
+            None => hash_condition.to_string(),
+        };
+        let (result, last_key) = if let Some(feed_id) = request.feed_id {
+            // entries of a single feed are queried from the index keyed by feedId,
+            // sorted by published; the limit applies before the unread filter,
+            // so the index is queried until the page is full or there is no more of it
+            let mut items = Vec::new();
+            let mut start_key = start_key;
+            loop {
+                let mut query = client
+                    .query()
+                    .table_name(&self.config.entries_table)
+                    .index_name(&self.config.feed_index)
+                    .key_condition_expression(key_condition("feedId = :feedId"))
+                    .expression_attribute_values(":feedId", to_attribute_value(feed_id)?)
+                    .scan_index_forward(false)
+                    .limit(limit - items.len() as i32)
+                    .set_exclusive_start_key(start_key);
+                if let Some(older_than) = &older_than {
+                    query = query.expression_attribute_values(":older", older_than.clone());
+                }
+                if request.unread_only {
+                    query = query
+                        .filter_expression("readTs = :read")
+                        .expression_attribute_values(":read", to_attribute_value(&0u64)?);
+                }
+                let output = query.send().await.map_err(|e| {
+                    warn!(error = ?e, ?feed_id, unread_only=?request.unread_only, "querying feed entries from index");
+                    e
+                })?;
+                items.extend(output.items.unwrap_or_default());
+                start_key = output.last_evaluated_key;
+                if items.len() as i32 >= limit || start_key.is_none() {
+                    break;
+                }
+            }
+            (Some(items), start_key)
+        } else if request.unread_only {
+            let mut query = client
+                .query()
+                .table_name(&self.config.entries_table)
+                .index_name(&self.config.read_status_index)
+                .key_condition_expression(key_condition("readTs = :read"))
+                // the newest entries go first
+                .scan_index_forward(false)
+                .limit(limit)
+                .set_exclusive_start_key(start_key);
+            if let Some(older_than) = &older_than {
+                query = query.expression_attribute_values(":older", older_than.clone());
+            }
+
+            query = query
+                //.filter_expression("#r = :read")
+                .expression_attribute_values(":read", to_attribute_value(&0u64)?);
+            let output = query.send().await.map_err(|e| {
+                warn!(error = ?e, unread_only=?request.unread_only, "querying entries from index");
+                e
+            })?;
+            (output.items, output.last_evaluated_key)
+        } else {
+            // for all entries request we just scan the entries table to retrieve all of them;
+            // the scan goes in the order of the hashes, so the entries are sorted within
+            // the page only, there is no index having all of them ordered by published
+            let mut query = client
+                .scan()
+                .table_name(&self.config.entries_table)
+                .index_name(&self.config.read_status_index)
+                .limit(limit)
+                .set_exclusive_start_key(start_key);
+            if let Some(older_than) = &older_than {
+                query = query
+                    .filter_expression("published < :older")
+                    .expression_attribute_values(":older", older_than.clone());
+            }
+            let output = query.send().await.map_err(|e| {
+                warn!(error = ?e, unread_only=?request.unread_only, "scanning entries in index");
+                e
+            })?;
+            (output.items, output.last_evaluated_key)
+        };
+        let next_cursor = last_key.map(EntriesCursor::encode).transpose()?;
+
+        let mut indexed_entries = if let Some(items) = result {
+            debug!("got {} items", items.len());
+            items
+        } else {
+            return Ok((Vec::new(), next_cursor));
+        };
+
+        for entry in &mut indexed_entries {
+            // each entry is already HashMap<String, AttributeValue> and is exactly what we want
+            // to pass to batchGet as entry keys, but with additional attributes
+            entry.retain(|k, _| k.as_str() == "entryId" || k.as_str() == "published");
+        }
+
+        let mut handlers = Vec::new();
+        for chunk in indexed_entries.chunks(100) {
+            handlers.push(spawn(batch_get_entries(
+                client.clone(),
+                self.config.entries_table.clone(),
+                Vec::from(chunk),
+            )));
+        }
+        // the storage is shared between threads, so the queries are awaited
+        // instead of running them on a LocalSet
+        let mut items = Vec::with_capacity(indexed_entries.len());
+        for handler in handlers {
+            items.extend(handler.await??);
+        }
+
+        let mut entries: Vec<StoredEntry> = from_items(items).map_err(|e| {
+            warn!(error = ?e, "error converting entries");
+            e
+        })?;
+        // batches return the entries in no particular order
+        entries.sort_by_key(|entry| std::cmp::Reverse((entry.published, entry.entry_id.0)));
+        Ok((entries, next_cursor))
+    }
+
+    async fn put_entry(&self, entry: StoredEntry) -> Result<PutOutcome, Error> {
+        let stored: Option<StoredEntry> = if entry.published_source.is_estimated() {
+            // the publish date of such entry is the time it was seen first,
+            // it is new on every refresh, so an entry must be searched without it
+            let existing = self
+                .client
+                .query()
+                .table_name(&self.config.entries_table)
+                .key_condition_expression("entryId = :entryId")
+                .expression_attribute_values(":entryId", to_attribute_value(&entry.entry_id)?)
+                .limit(1)
+                .send()
+                .await?;
+            existing
+                .items
+                .and_then(|items| items.into_iter().next())
+                .map(from_item)
+                .transpose()?
+        } else {
+            self.client
+                .get_item()
+                .table_name(&self.config.entries_table)
+                .key("entryId", to_attribute_value(&entry.entry_id)?)
+                .key("published", to_attribute_value(&entry.published)?)
+                .send()
+                .await?
+                .item
+                .map(from_item)
+                .transpose()?
+        };
+        let (entry, outcome, condition, expression_values) = match stored {
+            None => (
+                entry,
+                PutOutcome::New,
+                "attribute_not_exists(entryId)",
+                None,
+            ),
+            // the entry must not be marked read or unread in the meantime,
+            // or that would be lost
+            Some(stored) => match updated_entry(&stored, entry) {
+                Some(entry) => (
+                    entry,
+                    PutOutcome::Updated,
+                    "readTs = :readTs",
+                    Some(HashMap::from([(
+                        ":readTs".to_string(),
+                        to_attribute_value(stored.read_ts)?,
+                    )])),
+                ),
+                None => return Ok(PutOutcome::Unchanged),
+            },
+        };
+        let dynamo_item = to_item(entry)?;
+        debug!(item = ?dynamo_item, "putting item");
+        let response = self
+            .client
+            .put_item()
+            .table_name(&self.config.entries_table)
+            .set_item(Some(dynamo_item))
+            .set_condition_expression(Some(condition.into()))
+            .set_expression_attribute_values(expression_values)
+            .set_return_values(Some(ReturnValue::None))
+            .send()
+            .await;
+        match response {
+            Ok(response) => {
+                debug!(response = ?response, "item written");
+                Ok(outcome)
+            }
+            // somebody else was faster, the entry is stored on the next refresh if it differs
+            Err(SdkError::ServiceError { ref err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                Ok(PutOutcome::Unchanged)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn set_read_ts(
+        &self,
+        entry_id: EntryID,
+        published: u64,
+        read_ts: i64,
+    ) -> Result<(), Error> {
+        // an update of a missing item would create it
+        let response = self
+            .client
+            .update_item()
+            .table_name(&self.config.entries_table)
+            .key("entryId", AttributeValue::N(format!("{}", entry_id.0)))
+            .key("published", AttributeValue::N(format!("{}", published)))
+            .update_expression("SET readTs = :readTs")
+            .condition_expression("attribute_exists(entryId)")
+            .expression_attribute_values(":readTs", AttributeValue::N(read_ts.to_string()))
+            .send()
+            .await;
+        match response {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError { ref err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                Err(entry_not_found(&entry_id, published))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// the scan only returns the entries left, so it is safe to run it again after a failure
+    async fn fix_entry_keys(&self) -> Result<(usize, usize), Error> {
+        let mut migrated = 0;
+        let mut failed = 0;
+        let mut start_key = None;
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.config.entries_table)
+                .filter_expression("attribute_not_exists(keyVersion) OR keyVersion < :version")
+                .expression_attribute_values(":version", to_attribute_value(ENTRY_KEY_VERSION)?)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(|e| {
+                    warn!(error = ?e, "error scanning entries in fixDatabase");
+                    e
+                })?;
+
+            for item in result.items.unwrap_or_default() {
+                match self.rekey_entry(item).await {
+                    Ok(()) => migrated += 1,
+                    Err(e) => {
+                        warn!(error = ?e, "error re-keying entry");
+                        failed += 1;
+                    }
+                }
+            }
+
+            start_key = result.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+        Ok((migrated, failed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::classify_error;
+
+    #[test]
+    fn test_entries_cursor() {
+        let key = HashMap::from([
+            ("entryId".to_string(), AttributeValue::N("42".to_string())),
+            (
+                "published".to_string(),
+                AttributeValue::N("1055217600".to_string()),
+            ),
+            ("readTs".to_string(), AttributeValue::N("0".to_string())),
+        ]);
+        let cursor = EntriesCursor::encode(key.clone()).unwrap();
+        assert_eq!(EntriesCursor::decode(&cursor).unwrap(), key);
+
+        // the feed index has no readTs in its keys
+        let key = HashMap::from([
+            ("entryId".to_string(), AttributeValue::N("42".to_string())),
+            (
+                "published".to_string(),
+                AttributeValue::N("1055217600".to_string()),
+            ),
+            ("feedId".to_string(), AttributeValue::N("7".to_string())),
+        ]);
+        let cursor = EntriesCursor::encode(key.clone()).unwrap();
+        assert_eq!(EntriesCursor::decode(&cursor).unwrap(), key);
+
+        let error = EntriesCursor::decode("garbage").unwrap_err();
+        assert_eq!(classify_error(error).code, ErrorCode::BadRequest);
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Error;
+use async_trait::async_trait;
+
+use model::entry::*;
+use model::events::*;
+use model::feed::*;
+
+use super::{entry_not_found, updated_entry, PositionCursor, PutOutcome, Storage};
+
+/// keeps everything in memory, for tests and local runs
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    feeds: Mutex<HashMap<FeedID, StoredFeed>>,
+    // keyed the same way as the DynamoDB table: by entry id and publish date
+    entries: Mutex<HashMap<(EntryID, u64), StoredEntry>>,
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn status(&self) -> StorageStatus {
+        StorageStatus::Ok
+    }
+
+    async fn all_feeds(&self) -> Result<Vec<StoredFeed>, Error> {
+        Ok(self.feeds.lock().unwrap().values().cloned().collect())
+    }
+
+    async fn feed(&self, feed_id: FeedID) -> Result<Option<StoredFeed>, Error> {
+        Ok(self.feeds.lock().unwrap().get(&feed_id).cloned())
+    }
+
+    async fn put_feed(&self, feed: &StoredFeed) -> Result<(), Error> {
+        self.feeds
+            .lock()
+            .unwrap()
+            .insert(feed.feed_id, feed.clone());
+        Ok(())
+    }
+
+    async fn remove_feed(&self, feed_id: FeedID) -> Result<(), Error> {
+        self.feeds.lock().unwrap().remove(&feed_id);
+        Ok(())
+    }
+
+    async fn remove_feed_entries(&self, feed_id: FeedID) -> Result<usize, Error> {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, entry| entry.feed_id != feed_id);
+        Ok(before - entries.len())
+    }
+
+    async fn entries(
+        &self,
+        request: &AllEntries,
+        limit: u32,
+    ) -> Result<(Vec<StoredEntry>, Option<String>), Error> {
+        let cursor = request
+            .cursor
+            .as_deref()
+            .map(PositionCursor::decode)
+            .transpose()?;
+        let position = |entry: &StoredEntry| (entry.published, entry.entry_id.0);
+
+        let mut entries: Vec<StoredEntry> = self
+            .entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| request.feed_id.map_or(true, |id| entry.feed_id == id))
+            .filter(|entry| !request.unread_only || entry.read_ts == 0)
+            .filter(|entry| {
+                request
+                    .older_than
+                    .map_or(true, |older_than| entry.published < older_than)
+            })
+            .filter(|entry| {
+                cursor.as_ref().map_or(true, |cursor| {
+                    position(entry) < (cursor.published, cursor.entry_id.0)
+                })
+            })
+            .cloned()
+            .collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(position(entry)));
+
+        let next_cursor = if entries.len() > limit as usize {
+            entries.truncate(limit as usize);
+            entries.last().map(PositionCursor::after)
+        } else {
+            None
+        };
+        Ok((entries, next_cursor.map(|c| c.encode()).transpose()?))
+    }
+
+    async fn put_entry(&self, entry: StoredEntry) -> Result<PutOutcome, Error> {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (entry.entry_id.clone(), entry.published);
+        let stored_key = if entry.published_source.is_estimated() {
+            entries
+                .keys()
+                .find(|(entry_id, _)| *entry_id == entry.entry_id)
+                .cloned()
+        } else {
+            entries.contains_key(&key).then_some(key.clone())
+        };
+        let stored_key = match stored_key {
+            Some(stored_key) => stored_key,
+            None => {
+                entries.insert(key, entry);
+                return Ok(PutOutcome::New);
+            }
+        };
+        match updated_entry(&entries[&stored_key], entry) {
+            Some(entry) => {
+                entries.insert(stored_key, entry);
+                Ok(PutOutcome::Updated)
+            }
+            None => Ok(PutOutcome::Unchanged),
+        }
+    }
+
+    async fn set_read_ts(
+        &self,
+        entry_id: EntryID,
+        published: u64,
+        read_ts: i64,
+    ) -> Result<(), Error> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(&(entry_id.clone(), published)) {
+            Some(entry) => {
+                entry.read_ts = read_ts;
+                Ok(())
+            }
+            None => Err(entry_not_found(&entry_id, published)),
+        }
+    }
+
+    async fn fix_entry_keys(&self) -> Result<(usize, usize), Error> {
+        let mut entries = self.entries.lock().unwrap();
+        let old_keys: Vec<_> = entries
+            .iter()
+            .filter(|(_, entry)| entry.key_version < ENTRY_KEY_VERSION)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let migrated = old_keys.len();
+        for key in old_keys {
+            let mut entry = entries.remove(&key).unwrap();
+            entry.entry_id = EntryID::scoped(entry.feed_id, entry.entry_id);
+            entry.key_version = ENTRY_KEY_VERSION;
+            // the read state of the entry stored with the new key wins, unless it is unread
+            let new_key = (entry.entry_id.clone(), entry.published);
+            if entries
+                .get(&new_key)
+                .map_or(true, |stored| stored.read_ts == 0)
+            {
+                entries.insert(new_key, entry);
+            }
+        }
+        Ok((migrated, 0))
+    }
+}
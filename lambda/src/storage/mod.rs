@@ -0,0 +1,139 @@
+//! storage of feeds and entries, the handlers get it injected by `route`,
+//! so they don't depend on any particular database
+
+mod dynamo;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::sync::Arc;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+use model::entry::*;
+use model::events::*;
+use model::feed::*;
+
+pub use dynamo::DynamoStorage;
+pub use memory::MemoryStorage;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// tells whether the storage is reachable, used by the handshake
+    async fn status(&self) -> StorageStatus;
+
+    async fn all_feeds(&self) -> Result<Vec<StoredFeed>, Error>;
+
+    async fn feed(&self, feed_id: FeedID) -> Result<Option<StoredFeed>, Error>;
+
+    /// creates the feed or replaces it with the new version
+    async fn put_feed(&self, feed: &StoredFeed) -> Result<(), Error>;
+
+    /// removes the feed only, its entries are left as they are
+    async fn remove_feed(&self, feed_id: FeedID) -> Result<(), Error>;
+
+    /// removes all entries of the feed, returns the number of entries removed
+    async fn remove_feed_entries(&self, feed_id: FeedID) -> Result<usize, Error>;
+
+    /// returns up to `limit` entries, the newest first, of the feed requested or of all of them,
+    /// published before `older_than` if it is set, along with the cursor of the next page,
+    /// if there could be one; author and category filters of the request are applied by the caller;
+    /// the pages are not required to follow each other in time when all the entries of all
+    /// the feeds are requested, read or not, only the entries in the page are the newest first
+    async fn entries(
+        &self,
+        request: &AllEntries,
+        limit: u32,
+    ) -> Result<(Vec<StoredEntry>, Option<String>), Error>;
+
+    /// stores the entry, or replaces the stored one if the feed has changed it,
+    /// keeping its read state; entries with estimated publish date are looked up
+    /// by their id only, since the date is new on every refresh
+    async fn put_entry(&self, entry: StoredEntry) -> Result<PutOutcome, Error>;
+
+    /// sets the time the entry was read at, zero marks it unread;
+    /// fails if the entry is not stored
+    async fn set_read_ts(
+        &self,
+        entry_id: EntryID,
+        published: u64,
+        read_ts: i64,
+    ) -> Result<(), Error>;
+
+    /// re-keys entries stored before entry ids were scoped by their feed,
+    /// returns the number of entries re-keyed and failed
+    async fn fix_entry_keys(&self) -> Result<(usize, usize), Error>;
+}
+
+/// what `put_entry` has done with the entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PutOutcome {
+    New,
+    Updated,
+    Unchanged,
+}
+
+/// the entry to store in place of the stored one, if the feed has changed it since;
+/// the read state and the publish date of the stored entry are kept
+fn updated_entry(stored: &StoredEntry, mut entry: StoredEntry) -> Option<StoredEntry> {
+    entry.read_ts = stored.read_ts;
+    entry.published = stored.published;
+    (entry != *stored).then_some(entry)
+}
+
+fn entry_not_found(entry_id: &EntryID, published: u64) -> Error {
+    anyhow::anyhow!(
+        "entry {} published at {} is not found",
+        entry_id.0,
+        published
+    )
+}
+
+/// chooses the storage by the STORAGE environment variable:
+/// "dynamodb" (the default), "memory" or "sqlite:<path to the database file>"
+pub async fn from_env(config: &Config) -> Result<Arc<dyn Storage>, Error> {
+    let storage = std::env::var("STORAGE").unwrap_or_default();
+    match storage.as_str() {
+        "" | "dynamodb" => Ok(Arc::new(DynamoStorage::from_env(config.clone()).await)),
+        "memory" => Ok(Arc::new(MemoryStorage::default())),
+        #[cfg(feature = "sqlite")]
+        path if path.starts_with("sqlite:") => {
+            Ok(Arc::new(SqliteStorage::open(&path["sqlite:".len()..])?))
+        }
+        storage => Err(anyhow::anyhow!("unsupported storage {:?}", storage)),
+    }
+}
+
+/// the position after the last entry of the page, for the storages ordering entries
+/// by themselves; clients get it serialized and should not make any assumptions about it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PositionCursor {
+    pub published: u64,
+    pub entry_id: EntryID,
+}
+
+impl PositionCursor {
+    pub fn after(entry: &StoredEntry) -> Self {
+        PositionCursor {
+            published: entry.published,
+            entry_id: entry.entry_id.clone(),
+        }
+    }
+
+    pub fn encode(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(cursor).map_err(|e| {
+            ErrorResponse::new(ErrorCode::BadRequest, format!("invalid cursor: {}", e))
+        })?)
+    }
+}
@@ -0,0 +1,381 @@
+use std::sync::Mutex;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::warn;
+
+use model::entry::*;
+use model::events::*;
+use model::feed::*;
+
+use super::{entry_not_found, updated_entry, PositionCursor, PutOutcome, Storage};
+
+// the records are kept as JSON, only the columns needed for lookups are split out;
+// ids are unsigned, so they are stored as their bits in signed integers
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS feeds (
+    feed_id INTEGER PRIMARY KEY,
+    feed TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS entries (
+    entry_id INTEGER NOT NULL,
+    published INTEGER NOT NULL,
+    feed_id INTEGER NOT NULL,
+    read_ts INTEGER NOT NULL DEFAULT 0,
+    key_version INTEGER NOT NULL DEFAULT 0,
+    entry TEXT NOT NULL,
+    PRIMARY KEY (entry_id, published)
+);
+CREATE INDEX IF NOT EXISTS entry_read_status ON entries (read_ts, published);
+CREATE INDEX IF NOT EXISTS entry_feed ON entries (feed_id, published);
+";
+
+/// keeps everything in a single SQLite database file, for running without AWS
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteStorage {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+fn insert_entry(conn: &Connection, sql: &str, entry: &StoredEntry) -> Result<usize, Error> {
+    Ok(conn.execute(
+        sql,
+        params![
+            entry.entry_id.0 as i64,
+            entry.published as i64,
+            entry.feed_id.0 as i64,
+            entry.read_ts,
+            entry.key_version,
+            serde_json::to_string(entry)?,
+        ],
+    )?)
+}
+
+/// the column is the source of truth for the read state, the JSON one is not updated
+fn entry_from_row(entry: String, read_ts: i64) -> Result<StoredEntry, Error> {
+    let mut entry: StoredEntry = serde_json::from_str(&entry)?;
+    entry.read_ts = read_ts;
+    Ok(entry)
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn status(&self) -> StorageStatus {
+        match self.conn.lock().unwrap().execute_batch("SELECT 1") {
+            Ok(()) => StorageStatus::Ok,
+            Err(e) => {
+                warn!(error = ?e, "storage is unavailable");
+                StorageStatus::Unavailable(e.to_string())
+            }
+        }
+    }
+
+    async fn all_feeds(&self) -> Result<Vec<StoredFeed>, Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare("SELECT feed FROM feeds")?;
+        let feeds = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|feed| Ok(serde_json::from_str(&feed?)?))
+            .collect();
+        feeds
+    }
+
+    async fn feed(&self, feed_id: FeedID) -> Result<Option<StoredFeed>, Error> {
+        let feed: Option<String> = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT feed FROM feeds WHERE feed_id = ?1",
+                [feed_id.0 as i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(feed.map(|feed| serde_json::from_str(&feed)).transpose()?)
+    }
+
+    async fn put_feed(&self, feed: &StoredFeed) -> Result<(), Error> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO feeds (feed_id, feed) VALUES (?1, ?2)",
+            params![feed.feed_id.0 as i64, serde_json::to_string(feed)?],
+        )?;
+        Ok(())
+    }
+
+    async fn remove_feed(&self, feed_id: FeedID) -> Result<(), Error> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM feeds WHERE feed_id = ?1", [feed_id.0 as i64])?;
+        Ok(())
+    }
+
+    async fn remove_feed_entries(&self, feed_id: FeedID) -> Result<usize, Error> {
+        Ok(self
+            .conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM entries WHERE feed_id = ?1", [feed_id.0 as i64])?)
+    }
+
+    async fn entries(
+        &self,
+        request: &AllEntries,
+        limit: u32,
+    ) -> Result<(Vec<StoredEntry>, Option<String>), Error> {
+        let cursor = request
+            .cursor
+            .as_deref()
+            .map(PositionCursor::decode)
+            .transpose()?;
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT entry, read_ts FROM entries
+            WHERE (?1 IS NULL OR feed_id = ?1)
+                AND (NOT ?2 OR read_ts = 0)
+                AND (?3 IS NULL OR (published, entry_id) < (?3, ?4))
+                AND (?5 IS NULL OR published < ?5)
+            ORDER BY published DESC, entry_id DESC
+            LIMIT ?6",
+        )?;
+        // one more entry tells whether there is the next page
+        let mut entries = statement
+            .query_map(
+                params![
+                    request.feed_id.map(|id| id.0 as i64),
+                    request.unread_only,
+                    cursor.as_ref().map(|cursor| cursor.published as i64),
+                    cursor.as_ref().map(|cursor| cursor.entry_id.0 as i64),
+                    request.older_than.map(|older_than| older_than as i64),
+                    limit + 1,
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?
+            .map(|row| {
+                let (entry, read_ts) = row?;
+                entry_from_row(entry, read_ts)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let next_cursor = if entries.len() > limit as usize {
+            entries.truncate(limit as usize);
+            entries.last().map(PositionCursor::after)
+        } else {
+            None
+        };
+        Ok((entries, next_cursor.map(|c| c.encode()).transpose()?))
+    }
+
+    async fn put_entry(&self, entry: StoredEntry) -> Result<PutOutcome, Error> {
+        let conn = self.conn.lock().unwrap();
+        let stored = conn
+            .query_row(
+                "SELECT entry, read_ts FROM entries
+                WHERE entry_id = ?1 AND (?3 OR published = ?2) LIMIT 1",
+                params![
+                    entry.entry_id.0 as i64,
+                    entry.published as i64,
+                    entry.published_source.is_estimated(),
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let stored = match stored {
+            Some((stored, read_ts)) => entry_from_row(stored, read_ts)?,
+            None => {
+                insert_entry(
+                    &conn,
+                    "INSERT OR IGNORE INTO entries (entry_id, published, feed_id, read_ts, key_version, entry)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    &entry,
+                )?;
+                return Ok(PutOutcome::New);
+            }
+        };
+        match updated_entry(&stored, entry) {
+            // the read state is left to its column
+            Some(entry) => {
+                conn.execute(
+                    "UPDATE entries SET entry = ?3 WHERE entry_id = ?1 AND published = ?2",
+                    params![
+                        entry.entry_id.0 as i64,
+                        entry.published as i64,
+                        serde_json::to_string(&entry)?,
+                    ],
+                )?;
+                Ok(PutOutcome::Updated)
+            }
+            None => Ok(PutOutcome::Unchanged),
+        }
+    }
+
+    async fn set_read_ts(
+        &self,
+        entry_id: EntryID,
+        published: u64,
+        read_ts: i64,
+    ) -> Result<(), Error> {
+        let updated = self.conn.lock().unwrap().execute(
+            "UPDATE entries SET read_ts = ?3 WHERE entry_id = ?1 AND published = ?2",
+            params![entry_id.0 as i64, published as i64, read_ts],
+        )?;
+        if updated == 0 {
+            return Err(entry_not_found(&entry_id, published));
+        }
+        Ok(())
+    }
+
+    async fn fix_entry_keys(&self) -> Result<(usize, usize), Error> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        let old_entries = transaction
+            .prepare("SELECT entry, read_ts FROM entries WHERE key_version < ?1")?
+            .query_map([ENTRY_KEY_VERSION], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, i64)>, _>>()?;
+
+        let mut migrated = 0;
+        let mut failed = 0;
+        for (entry, read_ts) in old_entries {
+            let rekeyed = entry_from_row(entry, read_ts).and_then(|mut entry| {
+                let (old_id, published) = (entry.entry_id.0 as i64, entry.published as i64);
+                entry.entry_id = EntryID::scoped(entry.feed_id, entry.entry_id);
+                entry.key_version = ENTRY_KEY_VERSION;
+                // the read state of the entry stored with the new key wins, unless it is unread
+                insert_entry(
+                    &transaction,
+                    "INSERT INTO entries (entry_id, published, feed_id, read_ts, key_version, entry)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                    ON CONFLICT (entry_id, published) DO UPDATE SET
+                        read_ts = excluded.read_ts, key_version = excluded.key_version,
+                        entry = excluded.entry
+                    WHERE read_ts = 0",
+                    &entry,
+                )?;
+                transaction.execute(
+                    "DELETE FROM entries WHERE entry_id = ?1 AND published = ?2",
+                    params![old_id, published],
+                )?;
+                Ok(())
+            });
+            match rekeyed {
+                Ok(()) => migrated += 1,
+                Err(e) => {
+                    warn!(error = ?e, "error re-keying entry");
+                    failed += 1;
+                }
+            }
+        }
+        transaction.commit()?;
+        Ok((migrated, failed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(entry_id: u64, published: u64) -> StoredEntry {
+        StoredEntry {
+            entry_id: EntryID(entry_id),
+            feed_id: FeedID(published % 2),
+            published,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_storage() {
+        let store = SqliteStorage::open_in_memory().unwrap();
+        assert_eq!(store.status().await, StorageStatus::Ok);
+
+        let feed = StoredFeed {
+            feed_id: FeedID(1),
+            url: "http://example.org/feed.rss".to_string(),
+            ..Default::default()
+        };
+        store.put_feed(&feed).await.unwrap();
+        store.put_feed(&feed).await.unwrap();
+        assert_eq!(store.all_feeds().await.unwrap(), vec![feed.clone()]);
+        assert_eq!(store.feed(FeedID(1)).await.unwrap(), Some(feed));
+        assert_eq!(store.feed(FeedID(2)).await.unwrap(), None);
+
+        for published in 1..=3 {
+            let outcome = store.put_entry(entry(published, published)).await.unwrap();
+            assert_eq!(outcome, PutOutcome::New);
+        }
+        store.set_read_ts(EntryID(3), 3, 1000).await.unwrap();
+        assert!(store.set_read_ts(EntryID(4), 4, 1000).await.is_err());
+        // stored entries are only replaced if they have changed, keeping their read state
+        let outcome = store.put_entry(entry(3, 3)).await.unwrap();
+        assert_eq!(outcome, PutOutcome::Unchanged);
+        let edited = StoredEntry {
+            title: Some("edited".to_string()),
+            ..entry(3, 3)
+        };
+        assert_eq!(store.put_entry(edited).await.unwrap(), PutOutcome::Updated);
+
+        let request = AllEntries {
+            unread_only: true,
+            ..Default::default()
+        };
+        let (entries, cursor) = store.entries(&request, 1).await.unwrap();
+        assert_eq!(entries[0].entry_id, EntryID(2));
+        let request = AllEntries { cursor, ..request };
+        let (entries, cursor) = store.entries(&request, 1).await.unwrap();
+        assert_eq!(entries[0].entry_id, EntryID(1));
+        assert_eq!(cursor, None);
+        let request = AllEntries {
+            older_than: Some(3),
+            ..Default::default()
+        };
+        let (entries, _) = store.entries(&request, 10).await.unwrap();
+        let ids: Vec<_> = entries.iter().map(|e| e.entry_id.0).collect();
+        assert_eq!(ids, vec![2, 1]);
+
+        let request = AllEntries {
+            feed_id: Some(FeedID(1)),
+            ..Default::default()
+        };
+        let (entries, _) = store.entries(&request, 10).await.unwrap();
+        let ids: Vec<_> = entries.iter().map(|e| (e.entry_id.0, e.read_ts)).collect();
+        assert_eq!(ids, vec![(3, 1000), (1, 0)]);
+
+        assert_eq!(store.remove_feed_entries(FeedID(1)).await.unwrap(), 2);
+        let (entries, _) = store.entries(&AllEntries::default(), 10).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        store.remove_feed(FeedID(1)).await.unwrap();
+        assert!(store.all_feeds().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_fix_entry_keys() {
+        let store = SqliteStorage::open_in_memory().unwrap();
+        let old = StoredEntry {
+            key_version: 0,
+            ..entry(1, 2)
+        };
+        store.put_entry(old).await.unwrap();
+
+        assert_eq!(store.fix_entry_keys().await.unwrap(), (1, 0));
+        let (entries, _) = store.entries(&AllEntries::default(), 10).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].entry_id, EntryID::scoped(FeedID(0), EntryID(1)));
+        assert_eq!(store.fix_entry_keys().await.unwrap(), (0, 0));
+    }
+}
@@ -1,4 +1,9 @@
-use aws_sdk_dynamodb::model::AttributeValue;
+use std::sync::Arc;
+
+use aws_sdk_dynamodb::error::{
+    BatchGetItemError, DeleteItemError, PutItemError, QueryError, ScanError, UpdateItemError,
+};
+use aws_sdk_dynamodb::types::SdkError;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -7,7 +12,8 @@ use model::events::Event;
 use anyhow::Error;
 
 use model::events::*;
-use tracing::warn;
+
+use crate::storage::Storage;
 
 /// events may come wrapped, for example when called using lambda function URL
 /// this is a parser, that unifies them down to our common event
@@ -16,6 +22,7 @@ use tracing::warn;
 #[serde(untagged)]
 pub enum WrappedEvent {
     UrlWrapped(LambdaUrlRequest),
+    Scheduled(ScheduledEvent),
     Just(Event),
     Unknown(Value),
 }
@@ -27,6 +34,14 @@ impl WrappedEvent {
                 Ok(event) => event,
                 Err(e) => Event::Bad(req.body.into(), format!("{:?}", e).into()),
             },
+            // feeds are refreshed in the background, only those which are due
+            WrappedEvent::Scheduled(event) if event.is_schedule() => {
+                Event::RefreshFeeds(RefreshFeeds { force: false })
+            }
+            WrappedEvent::Scheduled(event) => match serde_json::to_vec(&event) {
+                Ok(data) => Event::Bad(data, "unknown scheduled event".into()),
+                Err(e) => Event::Bad(Vec::new(), format!("{:?}", e).into()),
+            },
             WrappedEvent::Just(event) => event,
             WrappedEvent::Unknown(value) => match serde_json::to_vec(&value) {
                 Ok(data) => Event::Bad(data, "unknown event".into()),
@@ -49,102 +64,157 @@ impl LambdaUrlRequest {
     }
 }
 
-pub async fn fix_database(_: ()) -> Result<Response, Error> {
-    Ok(Response::new_ok("no fixes requred at the moment"))
-    //use aws_sdk_dynamodb::{model::AttributeAction, model::AttributeValueUpdate, Client};
-    //let shared_config = aws_config::load_from_env().await;
-    //let client = Client::new(&shared_config);
-    //let result = client
-    //.scan()
-    //.table_name("albibek-rss-entries")
-    //////.filter_expression("attribute_not_exists(feedId)")
-    ////.filter_expression("attribute_exists(backendOnly)")
-    //.projection_expression("entryId, published, #r")
-    //.expression_attribute_names("#r", "read")
-    ////.select(aws_sdk_dynamodb::model::Select::AllProjectedAttributes)
-    ////.limit(3)
-    //.send()
-    //.await
-    //.map_err(|e| {
-    //warn!(error = ?e, "error querying entries in fixDatabase");
-    //e
-    //})?;
-
-    //let now = chrono::Utc::now();
-
-    //let now = now.timestamp_millis().to_string();
-    //warn!(result = ?&result, "fix result");
-    //if let Some(mut entries) = result.items {
-    //while let Some(entry) = entries.pop() {
-    //warn!("entry: {:?}", entry);
-    //let result = client
-    //.update_item()
-    //.table_name("albibek-rss-entries")
-    //.key("entryId", entry.get("entryId").map(|v| v.clone()).unwrap())
-    //.key(
-    //"published",
-    //entry.get("published").map(|v| v.clone()).unwrap(),
-    //)
-    //.attribute_updates(
-    //"read",
-    //AttributeValueUpdate::builder()
-    //////.value(AttributeValue::N("1".to_string()))
-    //.action(AttributeAction::Delete)
-    //.build(),
-    //)
-    ////.expression_attribute_names("#r", "read")
-    //.send()
-    //.await;
-
-    //warn!("update result for {:?}: {:?}", entry, result);
-
-    ////let new_read = if let AttributeValue::Bool(v) =
-    ////entry.get("read").unwrap_or(&AttributeValue::Bool(false))
-    ////{
-    ////if *v {
-    ////now.clone()
-    ////} else {
-    ////"0".to_string()
-    ////}
-    ////} else {
-    ////"0".to_string()
-    //////panic!("WTF");
-    ////};
-    ////let result = client
-    ////.update_item()
-    ////.table_name("albibek-rss-entries")
-    ////.key("entryId", entry.get("entryId").map(|v| v.clone()).unwrap())
-    ////.key(
-    ////"published",
-    ////entry.get("published").map(|v| v.clone()).unwrap(),
-    ////)
-    ////.attribute_updates(
-    ////"readTs",
-    ////AttributeValueUpdate::builder()
-    ////.value(AttributeValue::N(new_read))
-    ////.action(AttributeAction::Put)
-    ////.build(),
-    ////)
-    ////.send()
-    ////.await;
-
-    ////warn!("update result for {:?}: {:?}", entry, result);
-    //}
-    //}
-
-    //Ok(Response::new_ok("table fixed"))
+/// the event EventBridge (or CloudWatch Events) rule sends on its schedule,
+/// unless the rule is set to send a constant input instead
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ScheduledEvent {
+    source: String,
+    #[serde(rename = "detail-type")]
+    detail_type: String,
+    #[serde(default)]
+    time: Option<String>,
+    #[serde(default)]
+    resources: Vec<String>,
+}
+
+impl ScheduledEvent {
+    pub fn is_schedule(&self) -> bool {
+        self.source == "aws.events" && self.detail_type == "Scheduled Event"
+    }
+}
+
+/// the handshake: tells the frontend what this backend is capable of
+/// and whether the storage is reachable
+pub async fn hello(store: Arc<dyn Storage>, _: ()) -> Result<Response, Error> {
+    Ok(Response::Hello(HelloResponse {
+        backend_version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: PROTOCOL_VERSION,
+        events: SUPPORTED_EVENTS.iter().map(|e| e.to_string()).collect(),
+        storage: store.status().await,
+    }))
+}
+
+/// error codes DynamoDB uses when the capacity of a table or an account is exceeded
+const THROTTLING_CODES: [&str; 3] = [
+    "ProvisionedThroughputExceededException",
+    "ThrottlingException",
+    "RequestLimitExceeded",
+];
+
+// seconds to wait before retrying throttled requests
+const THROTTLED_RETRY_AFTER: u64 = 5;
+
+/// turns the error happened while handling an event into the error response;
+/// errors which were not tagged by `ErrorResponse` on their way up are classified by type
+pub fn classify_error(error: Error) -> ErrorResponse {
+    if let Some(response) = error.downcast_ref::<ErrorResponse>() {
+        return response.clone();
+    }
+
+    // DynamoDB errors are of a separate type for each operation
+    macro_rules! classify_storage_error {
+        ($($operation_error:ty),+) => {
+            $(
+                if let Some(e) = error.downcast_ref::<SdkError<$operation_error>>() {
+                    let code = match e {
+                        SdkError::ServiceError { err, .. } => err.code(),
+                        _ => None,
+                    };
+                    return storage_error(code, e.to_string());
+                }
+            )+
+        };
+    }
+    classify_storage_error!(
+        BatchGetItemError,
+        DeleteItemError,
+        PutItemError,
+        QueryError,
+        ScanError,
+        UpdateItemError
+    );
+
+    #[cfg(feature = "sqlite")]
+    if let Some(e) = error.downcast_ref::<rusqlite::Error>() {
+        return storage_error(None, e.to_string());
+    }
+
+    ErrorResponse::new(ErrorCode::Internal, format!("{:#}", error))
+}
+
+fn storage_error(code: Option<&str>, message: String) -> ErrorResponse {
+    match code {
+        Some(code) if THROTTLING_CODES.contains(&code) => {
+            ErrorResponse::new(ErrorCode::Throttled, message)
+                .with_retry_after(THROTTLED_RETRY_AFTER)
+        }
+        _ => ErrorResponse::new(ErrorCode::StorageError, message),
+    }
+}
+
+/// re-keys entries stored before entry ids were scoped by their feed
+pub async fn fix_database(store: Arc<dyn Storage>, _: ()) -> Result<Response, Error> {
+    let (migrated, failed) = store.fix_entry_keys().await?;
+    Ok(Response::new_ok(format!(
+        "re-keyed {} entries, {} failed",
+        migrated, failed
+    )))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::init_tracing;
+    use crate::storage::MemoryStorage;
+    use model::entry::*;
+    use model::feed::FeedID;
     use tracing::debug;
 
+    #[test]
+    fn test_classify_error() {
+        let tagged = Error::from(ErrorResponse::new(ErrorCode::ParseFailed, "bad feed"))
+            .context("adding feed");
+        assert_eq!(classify_error(tagged).code, ErrorCode::ParseFailed);
+
+        let throttled = storage_error(Some("ThrottlingException"), "slow down".to_string());
+        assert_eq!(throttled.code, ErrorCode::Throttled);
+        assert_eq!(throttled.retry_after, Some(THROTTLED_RETRY_AFTER));
+
+        let unknown = classify_error(anyhow::anyhow!("something happened"));
+        assert_eq!(unknown.code, ErrorCode::Internal);
+    }
+
     #[tokio::test]
     async fn test_fix_database() {
         init_tracing(true).unwrap();
-        let response = fix_database(()).await.unwrap();
+        let store = Arc::new(MemoryStorage::default());
+        // stored before the entry ids were scoped
+        let entry = StoredEntry {
+            entry_id: EntryID(1),
+            feed_id: FeedID(2),
+            key_version: 0,
+            ..Default::default()
+        };
+        store.put_entry(entry).await.unwrap();
+
+        let response = fix_database(store.clone(), ()).await.unwrap();
         debug!(?response, "fix_database");
+        let (entries, _) = store.entries(&AllEntries::default(), 10).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].entry_id, EntryID::scoped(FeedID(2), EntryID(1)));
+        assert_eq!(entries[0].key_version, ENTRY_KEY_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_hello() {
+        init_tracing(true).unwrap();
+        let store = Arc::new(MemoryStorage::default());
+        match hello(store, ()).await.unwrap() {
+            Response::Hello(hello) => {
+                assert!(hello.is_compatible());
+                assert_eq!(hello.storage, StorageStatus::Ok);
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
     }
 }
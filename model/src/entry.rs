@@ -7,15 +7,92 @@ use crate::feed::FeedID;
 use atom_syndication::Entry as AtomEntry;
 
 #[cfg(feature = "backend")]
-use rss::{Guid, Item};
+use rss::{extension::ExtensionMap, Enclosure, Guid, Item};
 
 #[cfg(feature = "backend")]
-use crate::util::hash;
+use crate::jsonfeed::JsonFeedItem;
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
+#[cfg(feature = "backend")]
+use crate::rdf::RdfItem;
+
+#[cfg(feature = "backend")]
+use crate::util::{hash, parse_date, parse_duration, rss_author_name, unique_names};
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize, Default)]
 #[serde(transparent)]
 pub struct EntryID(pub u64);
 
+/// version of the entry key derivation, see `EntryID::new`;
+/// entries stored before the versioning have version 0 with unscoped ids
+pub const ENTRY_KEY_VERSION: u32 = 1;
+
+#[cfg(feature = "backend")]
+impl EntryID {
+    /// ids provided by feeds are only unique inside the feed: generic guids like "1"
+    /// and links shared by aggregators collide otherwise, so the id is scoped by the feed
+    pub fn new(feed_id: FeedID, ext_id: &str) -> Self {
+        Self::scoped(feed_id, EntryID(hash(ext_id.as_bytes())))
+    }
+
+    /// derives the scoped id from the unscoped one, which is all we have for entries
+    /// stored with key version 0
+    pub fn scoped(feed_id: FeedID, unscoped: EntryID) -> Self {
+        let mut data = feed_id.0.to_le_bytes().to_vec();
+        data.extend_from_slice(&unscoped.0.to_le_bytes());
+        EntryID(hash(&data))
+    }
+}
+
+/// where the publish date of an entry was taken from
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum PublishedSource {
+    /// the entry's own publish date: RSS pubDate, Atom and JSON Feed published
+    PubDate,
+    /// Dublin Core dc:date
+    DcDate,
+    /// the date of the last update: atom:updated or JSON Feed date_modified
+    Updated,
+    /// the entry had no usable date, so the time it was first seen is used
+    FirstSeen,
+}
+
+impl Default for PublishedSource {
+    fn default() -> Self {
+        // all entries stored before the field was introduced had pubDate
+        PublishedSource::PubDate
+    }
+}
+
+impl PublishedSource {
+    pub fn is_estimated(&self) -> bool {
+        *self == PublishedSource::FirstSeen
+    }
+}
+
+/// a file attached to an entry, like a podcast episode or a video
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    pub url: String,
+    pub mime_type: Option<String>,
+    // size in bytes
+    pub length: Option<u64>,
+    pub title: Option<String>,
+    // duration in seconds
+    pub duration: Option<u64>,
+}
+
+/// episode details from the iTunes podcast namespace
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PodcastInfo {
+    // duration in seconds
+    pub duration: Option<u64>,
+    pub episode: Option<String>,
+    pub image: Option<String>,
+}
+
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct StoredEntry {
@@ -24,12 +101,46 @@ pub struct StoredEntry {
     pub feed_id: FeedID,
 
     pub published: u64,
+    #[serde(default)]
+    pub published_source: PublishedSource,
     // id provided with feed
     pub link: Option<String>,
     pub title: Option<String>,
     pub description: Option<String>,
     pub content: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    #[serde(default)]
+    pub podcast: Option<PodcastInfo>,
     pub read_ts: i64,
+    #[serde(default)]
+    pub key_version: u32,
+}
+
+impl Default for StoredEntry {
+    fn default() -> Self {
+        StoredEntry {
+            entry_id: EntryID::default(),
+            feed_id: FeedID::default(),
+            published: 0,
+            published_source: PublishedSource::default(),
+            link: None,
+            title: None,
+            description: None,
+            content: None,
+            authors: Vec::new(),
+            categories: Vec::new(),
+            attachments: Vec::new(),
+            podcast: None,
+            read_ts: 0,
+            // unlike the entries stored before the versions, the new ones have the current key
+            key_version: ENTRY_KEY_VERSION,
+        }
+    }
 }
 
 #[cfg(feature = "backend")]
@@ -42,38 +153,314 @@ impl StoredEntry {
             guid,
             link,
             pub_date,
+            author,
+            categories,
+            dublin_core_ext,
+            enclosure,
+            itunes_ext,
+            extensions,
             ..
         } = item;
 
         let entry_id = if let Some(guid) = guid {
-            hash(guid.value.as_bytes())
+            EntryID::new(feed_id, &guid.value)
         } else {
             if let Some(link) = &link {
-                hash(link.as_bytes())
+                EntryID::new(feed_id, link)
             } else {
                 return Err(anyhow!("link is a required field"));
             }
         };
-        let published = if let Some(pub_date) = pub_date {
-            let date = chrono::DateTime::parse_from_rfc2822(&pub_date)?;
-            date.timestamp() as u64
-        } else {
-            return Err(anyhow!("unknown publish date is not supported"));
-        };
+        let dc = dublin_core_ext.unwrap_or_default();
+        let dc_date = dc.dates.into_iter().next();
+        let authors = unique_names(author.map(rss_author_name).into_iter().chain(dc.creators));
+        let categories = unique_names(
+            categories
+                .into_iter()
+                .map(|category| category.name)
+                .chain(dc.subjects),
+        );
+        // without the atom feature of rss crate, atom elements are stored under their prefix
+        let atom_updated = extensions
+            .get("atom")
+            .and_then(|atom| atom.get("updated"))
+            .and_then(|updated| updated.first())
+            .and_then(|updated| updated.value.clone());
+        let (published, published_source) = pick_published([
+            (PublishedSource::PubDate, pub_date),
+            (PublishedSource::DcDate, dc_date),
+            (PublishedSource::Updated, atom_updated),
+        ]);
+
+        let podcast = itunes_ext.map(|itunes| PodcastInfo {
+            duration: itunes.duration.as_deref().and_then(parse_duration),
+            episode: itunes.episode,
+            image: itunes.image,
+        });
+        let mut attachments = Vec::new();
+        if let Some(Enclosure {
+            url,
+            length,
+            mime_type,
+        }) = enclosure
+        {
+            attachments.push(Attachment {
+                url,
+                mime_type: Some(mime_type).filter(|mime_type| !mime_type.is_empty()),
+                // zero is used by many feeds when the length is unknown
+                length: length.trim().parse().ok().filter(|length| *length > 0),
+                title: None,
+                duration: podcast.as_ref().and_then(|podcast| podcast.duration),
+            });
+        }
+        for attachment in media_attachments(&extensions) {
+            // the enclosure is often duplicated as media:content
+            if !attachments.iter().any(|a| a.url == attachment.url) {
+                attachments.push(attachment);
+            }
+        }
+
         Ok(Self {
-            entry_id: EntryID(entry_id),
+            entry_id,
             feed_id,
             title,
             description,
             content,
             link,
             published,
+            published_source,
+            authors,
+            categories,
+            attachments,
+            podcast,
             // entries are unread by default
             read_ts: 0,
+            key_version: ENTRY_KEY_VERSION,
+        })
+    }
+
+    pub fn from_atom_feed_entry(feed_id: FeedID, entry: AtomEntry) -> Result<Self, Error> {
+        let AtomEntry {
+            title,
+            id,
+            updated,
+            published,
+            links,
+            summary,
+            content,
+            authors,
+            categories,
+            ..
+        } = entry;
+
+        if id.is_empty() {
+            return Err(anyhow!("id is a required field"));
+        }
+
+        // the alternate link is the one pointing to the entry itself,
+        // if there is no such, the first one is the best guess we have
+        let link = links
+            .iter()
+            .find(|link| link.rel == "alternate")
+            .or_else(|| links.first())
+            .map(|link| link.href.clone());
+        let attachments = links
+            .iter()
+            .filter(|link| link.rel == "enclosure")
+            .map(|link| Attachment {
+                url: link.href.clone(),
+                mime_type: link.mime_type.clone(),
+                length: link
+                    .length
+                    .as_deref()
+                    .and_then(|length| length.parse().ok()),
+                title: link.title.clone(),
+                duration: None,
+            })
+            .collect();
+
+        // atom requires updated to be present, so it's a fallback for an optional published
+        let (published, published_source) = match published {
+            Some(published) => (published, PublishedSource::PubDate),
+            None => (updated, PublishedSource::Updated),
+        };
+
+        Ok(Self {
+            entry_id: EntryID::new(feed_id, &id),
+            feed_id,
+            title: Some(title.value),
+            description: summary.map(|summary| summary.value),
+            content: content.and_then(|content| content.value),
+            link,
+            published: published.timestamp() as u64,
+            published_source,
+            authors: unique_names(authors.into_iter().map(|author| author.name)),
+            categories: unique_names(categories.into_iter().map(|category| category.term)),
+            attachments,
+            podcast: None,
+            read_ts: 0,
+            key_version: ENTRY_KEY_VERSION,
+        })
+    }
+
+    pub fn from_json_feed_item(feed_id: FeedID, item: JsonFeedItem) -> Result<Self, Error> {
+        let JsonFeedItem {
+            id,
+            url,
+            external_url,
+            title,
+            content_html,
+            content_text,
+            summary,
+            date_published,
+            date_modified,
+            authors,
+            author,
+            tags,
+            attachments,
+            ..
+        } = item;
+
+        let link = url.or(external_url);
+        let entry_id = match id.filter(|id| !id.is_empty()) {
+            Some(id) => EntryID::new(feed_id, &id),
+            None => match &link {
+                Some(link) => EntryID::new(feed_id, link),
+                None => return Err(anyhow!("item has neither id nor url")),
+            },
+        };
+
+        let (published, published_source) = pick_published([
+            (PublishedSource::PubDate, date_published),
+            (PublishedSource::Updated, date_modified),
+        ]);
+        let attachments = attachments
+            .into_iter()
+            .map(|attachment| Attachment {
+                url: attachment.url,
+                mime_type: attachment.mime_type,
+                length: attachment.size_in_bytes,
+                title: attachment.title,
+                duration: attachment
+                    .duration_in_seconds
+                    .map(|duration| duration.round() as u64),
+            })
+            .collect();
+
+        Ok(Self {
+            entry_id,
+            feed_id,
+            title,
+            description: summary,
+            content: content_html.or(content_text),
+            link,
+            published,
+            published_source,
+            authors: unique_names(
+                authors
+                    .into_iter()
+                    .chain(author)
+                    .filter_map(|author| author.name),
+            ),
+            categories: unique_names(tags),
+            attachments,
+            podcast: None,
+            read_ts: 0,
+            key_version: ENTRY_KEY_VERSION,
         })
     }
 
-    pub fn from_atom_feed_entry(entry: AtomEntry) -> Result<(), Error> {
-        Err(anyhow!("atom feed not implemented yet"))
+    pub fn from_rdf_item(feed_id: FeedID, item: RdfItem) -> Result<Self, Error> {
+        let RdfItem {
+            about,
+            title,
+            link,
+            description,
+            content,
+            dc_date,
+            dc_creators,
+            dc_subjects,
+        } = item;
+
+        let entry_id = if let Some(about) = &about {
+            EntryID::new(feed_id, about)
+        } else if let Some(link) = &link {
+            EntryID::new(feed_id, link)
+        } else {
+            return Err(anyhow!("link is a required field"));
+        };
+
+        let (published, published_source) = pick_published([(PublishedSource::DcDate, dc_date)]);
+
+        Ok(Self {
+            entry_id,
+            feed_id,
+            title,
+            description,
+            content,
+            link,
+            published,
+            published_source,
+            authors: unique_names(dc_creators),
+            categories: unique_names(dc_subjects),
+            attachments: Vec::new(),
+            podcast: None,
+            read_ts: 0,
+            key_version: ENTRY_KEY_VERSION,
+        })
     }
 }
+
+/// collects Media RSS media:content elements, both standalone and grouped by media:group,
+/// see https://www.rssboard.org/media-rss
+#[cfg(feature = "backend")]
+fn media_attachments(extensions: &ExtensionMap) -> Vec<Attachment> {
+    let media = match extensions.get("media") {
+        Some(media) => media,
+        None => return Vec::new(),
+    };
+    let grouped = media
+        .get("group")
+        .into_iter()
+        .flatten()
+        .flat_map(|group| group.children.get("content").into_iter().flatten());
+    media
+        .get("content")
+        .into_iter()
+        .flatten()
+        .chain(grouped)
+        .filter_map(|content| {
+            let attr = |name: &str| content.attrs.get(name).cloned();
+            Some(Attachment {
+                url: attr("url")?,
+                mime_type: attr("type"),
+                length: attr("fileSize").and_then(|size| size.parse().ok()),
+                title: content
+                    .children
+                    .get("title")
+                    .and_then(|title| title.first())
+                    .and_then(|title| title.value.clone()),
+                duration: attr("duration").and_then(|duration| duration.parse().ok()),
+            })
+        })
+        .collect()
+}
+
+/// takes the first date that could be parsed from the candidates in the order of preference,
+/// the current time is used when none of them is usable
+#[cfg(feature = "backend")]
+fn pick_published<const N: usize>(
+    candidates: [(PublishedSource, Option<String>); N],
+) -> (u64, PublishedSource) {
+    candidates
+        .into_iter()
+        .filter_map(|(source, date)| Some((parse_date(&date?).ok()?, source)))
+        .next()
+        .map(|(date, source)| (date.timestamp() as u64, source))
+        .unwrap_or_else(|| {
+            (
+                chrono::Utc::now().timestamp() as u64,
+                PublishedSource::FirstSeen,
+            )
+        })
+}
@@ -5,14 +5,40 @@ use serde_json::from_slice;
 use crate::entry::*;
 use crate::feed::*;
 
+/// version of the protocol between the frontend and the backend,
+/// it is bumped on every incompatible change of `Event` or `Response`
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// events handled by the backend, as they are named in JSON
+pub const SUPPORTED_EVENTS: [&str; 11] = [
+    "hello",
+    "refreshFeeds",
+    "allEntries",
+    "allFeeds",
+    "addFeed",
+    "previewFeed",
+    "removeFeed",
+    "updateFeed",
+    "markReadUnread",
+    "markAllRead",
+    "fixDatabase",
+];
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum Event {
+    /// the handshake, sent before anything else to check the backend is compatible
+    Hello(()),
     RefreshFeeds(RefreshFeeds),
     AllEntries(AllEntries),
     AllFeeds(()),
     AddFeed(String),
+    /// fetches and parses the feed like `AddFeed` does, but does not store anything
+    PreviewFeed(String),
+    RemoveFeed(RemoveFeed),
+    UpdateFeed(UpdateFeed),
     MarkReadUnread(Vec<(EntryID, u64, bool)>),
+    MarkAllRead(MarkAllRead),
     FixDatabase(()),
     #[serde(skip)]
     // this one is never created over deserialization, only created by hands
@@ -28,22 +54,305 @@ pub struct RefreshFeeds {
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct RemoveFeed {
+    pub feed_id: FeedID,
+    // the entries of the feed are kept, unless asked otherwise
+    #[serde(default)]
+    pub remove_entries: bool,
+}
+
+/// changes the settings given, the ones missing are left as they are
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateFeed {
+    pub feed_id: FeedID,
+    #[serde(default)]
+    pub url: Option<String>,
+    // an empty title removes the override
+    #[serde(default)]
+    pub title_override: Option<String>,
+    #[serde(default)]
+    pub paused: Option<bool>,
+    // milliseconds between the fetches, zero returns to the learned interval
+    #[serde(default)]
+    pub poll_interval: Option<u64>,
+}
+
+/// marks read the unread entries of the feed, or of all the feeds if it's not set;
+/// only the entries published before `older_than`, in seconds, are marked if it is set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkAllRead {
+    #[serde(default)]
+    pub feed_id: Option<FeedID>,
+    #[serde(default)]
+    pub older_than: Option<u64>,
+}
+
+impl MarkAllRead {
+    /// tells whether the entry is one of those the request is about, read or not
+    pub fn matches(&self, entry: &StoredEntry) -> bool {
+        self.feed_id
+            .map_or(true, |feed_id| entry.feed_id == feed_id)
+            && self
+                .older_than
+                .map_or(true, |older_than| entry.published < older_than)
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
 pub struct AllEntries {
     #[serde(default)]
     pub unread_only: bool,
 
     #[serde(default)]
     pub feed_id: Option<FeedID>,
+
+    // entries by any of these authors are not returned
+    #[serde(default)]
+    pub exclude_authors: Vec<String>,
+
+    // if not empty, only entries having at least one of these categories are returned
+    #[serde(default)]
+    pub categories: Vec<String>,
+
+    // only the entries published before this time, in seconds like `published`, if it is set
+    #[serde(default)]
+    pub older_than: Option<u64>,
+
+    // maximum number of entries in the page, the backend's default is used when missing;
+    // the page can be shorter when the filters match few entries, even if there are more
+    #[serde(default)]
+    pub limit: Option<u32>,
+
+    // the page to return, as given by `EntriesPage::next_cursor`, the first page if missing
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
+impl AllEntries {
+    /// checks the entry against the author and category filters, names are compared
+    /// case-insensitively since feeds are not consistent about it
+    pub fn matches(&self, entry: &StoredEntry) -> bool {
+        let contains = |list: &[String], name: &String| {
+            list.iter()
+                .any(|item| item.to_lowercase() == name.to_lowercase())
+        };
+        let excluded = entry
+            .authors
+            .iter()
+            .any(|author| contains(&self.exclude_authors, author));
+        let categorized = self.categories.is_empty()
+            || entry
+                .categories
+                .iter()
+                .any(|category| contains(&self.categories, category));
+        !excluded && categorized
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
-    AllEntries(Vec<StoredEntry>),
+    AllEntries(EntriesPage),
     AllFeeds(Vec<StoredFeed>),
-    MarkedRead(Vec<(EntryID, u64, i64)>),
+    // the page added is not a feed, but it links these ones
+    FeedCandidates(Vec<FeedCandidate>),
+    FeedPreview(FeedPreview),
+    RefreshReport(RefreshReport),
+    MarkedRead(Vec<MarkedEntry>),
+    MarkAllReadReport(MarkAllReadReport),
     Ok(OkResponse),
+    Error(ErrorResponse),
+    Hello(HelloResponse),
+}
+
+/// the entries are the newest first, but when all of them are requested, read or not,
+/// the next page may have newer ones than this, so the pages loaded are to be merged in order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntriesPage {
+    pub entries: Vec<StoredEntry>,
+    // the cursor of the next page, there are no more entries if it is missing
+    pub next_cursor: Option<String>,
+    // the cursor this page was requested with
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedPreview {
+    pub feed: StoredFeed,
+    // the first entries of the feed, as they would be stored
+    pub entries: Vec<StoredEntry>,
+    // the number of entries parsed, including the ones not returned
+    pub total_entries: usize,
+    pub errors: Vec<ItemError>,
+}
+
+/// the entry marked read or unread, its read state is left as it was if there is an error
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkedEntry {
+    pub entry_id: EntryID,
+    pub published: u64,
+    // the time the entry is read at, zero if it is unread
+    pub read_ts: i64,
+    #[serde(default)]
+    pub error: Option<String>,
 }
 
+/// what `MarkAllRead` has done; there may be too many entries marked to list them,
+/// so only the ones failed are listed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkAllReadReport {
+    // the entries loaded by the frontend are marked read according to it
+    pub request: MarkAllRead,
+    // the time the entries are read at
+    pub read_ts: i64,
+    pub marked: usize,
+    pub failed: Vec<MarkedEntry>,
+}
+
+/// what `RefreshFeeds` has done with every feed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshReport {
+    pub feeds: Vec<FeedReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedReport {
+    pub feed_id: FeedID,
+    // the title shown for the feed, or its url if it has none
+    pub title: String,
+    pub outcome: RefreshOutcome,
+    pub new_entries: usize,
+    // entries already stored, but changed by the feed since then
+    pub updated_entries: usize,
+    // items of the feed which could not be turned into entries
+    pub dropped: Vec<ItemError>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RefreshOutcome {
+    Fetched,
+    NotModified,
+    // the feed is not due yet or it is paused
+    Skipped,
+    Failed(FeedError),
+}
+
+impl RefreshReport {
+    /// a line for the user, like "3 new in A, B failed: timeout"
+    pub fn summary(&self) -> String {
+        let parts: Vec<String> = self
+            .feeds
+            .iter()
+            .filter_map(|feed| match &feed.outcome {
+                RefreshOutcome::Failed(error) => {
+                    Some(format!("{} failed: {}", feed.title, error.message))
+                }
+                _ if feed.new_entries > 0 => {
+                    Some(format!("{} new in {}", feed.new_entries, feed.title))
+                }
+                _ => None,
+            })
+            .collect();
+        if parts.is_empty() {
+            "nothing new".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+/// an item of the feed which cannot be turned into an entry, so it is skipped
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemError {
+    // the position of the item in the feed, starting with zero
+    pub index: usize,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HelloResponse {
+    pub backend_version: String,
+    pub protocol_version: u32,
+    pub events: Vec<String>,
+    pub storage: StorageStatus,
+}
+
+impl HelloResponse {
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version == PROTOCOL_VERSION
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StorageStatus {
+    Ok,
+    Unavailable(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorCode {
+    /// the event could not be deserialized or has invalid values
+    BadRequest,
+    /// the feed server could not be reached or returned an error
+    FeedFetchFailed,
+    /// the feed was fetched, but is not a feed in any of the supported formats
+    ParseFailed,
+    StorageError,
+    /// the storage is over its capacity, the request should be retried later
+    Throttled,
+    /// the frontend and the backend speak different versions of the protocol
+    ProtocolMismatch,
+    /// anything not falling into the categories above
+    Internal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String,
+    // seconds to wait before retrying the request
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+}
+
+impl ErrorResponse {
+    pub fn new<S: Into<String>>(code: ErrorCode, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+}
+
+impl std::fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ErrorResponse {}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OkResponse {
     message: String,
@@ -59,6 +368,10 @@ impl Response {
     pub fn new_ok<S: Into<String>>(s: S) -> Self {
         Response::Ok(OkResponse::new(s))
     }
+
+    pub fn new_error<S: Into<String>>(code: ErrorCode, s: S) -> Self {
+        Response::Error(ErrorResponse::new(code, s))
+    }
 }
 
 impl<'a> TryFrom<&'a [u8]> for Event {
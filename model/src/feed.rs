@@ -1,17 +1,29 @@
-use anyhow::{anyhow, Error};
+use anyhow::Error;
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "backend")]
 use atom_syndication::Feed as AtomFeed;
 
+#[cfg(feature = "backend")]
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc, Weekday};
+
 #[cfg(feature = "backend")]
 use rss::Channel;
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy, Hash)]
+#[cfg(feature = "backend")]
+use crate::jsonfeed::JsonFeed;
+
+#[cfg(feature = "backend")]
+use crate::rdf::RdfChannel;
+
+#[cfg(feature = "backend")]
+use crate::util::{parse_date, unique_names};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy, Hash, Default)]
 #[serde(transparent)]
 pub struct FeedID(pub u64);
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct StoredFeed {
     // internal properties of database
@@ -25,25 +37,319 @@ pub struct StoredFeed {
     pub description: Option<String>,
     // last update time by version of feed authors
     pub ext_last_update: Option<u64>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+
+    // refresh hints provided with feed
+    // minutes the feed may be cached for, RSS <ttl>
+    pub ttl: Option<u64>,
+    // hours in GMT, when the feed should not be fetched, RSS <skipHours>
+    #[serde(default)]
+    pub skip_hours: Vec<u32>,
+    // days (counted from Monday), when the feed should not be fetched, RSS <skipDays>
+    #[serde(default)]
+    pub skip_days: Vec<u32>,
+    // milliseconds between the feed updates, calculated from sy:updatePeriod and sy:updateFrequency
+    pub update_interval: Option<u64>,
+    // the feed should not be fetched before this time
+    pub next_fetch: Option<u64>,
+    // milliseconds between the fetches, learned from how often the feed gets new entries
+    #[serde(default)]
+    pub poll_interval: Option<u64>,
+    // milliseconds between the fetches set by the user, used instead of the learned one
+    #[serde(default)]
+    pub poll_interval_override: Option<u64>,
+
+    // validators of the last fetch, sent back to make the request conditional
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    // hash of the body fetched last time, for the servers which don't support conditional requests
+    pub body_hash: Option<u64>,
+
+    // set by the user, shown instead of the title given by the feed
+    #[serde(default)]
+    pub title_override: Option<String>,
+    // paused feeds are not refreshed
+    #[serde(default)]
+    pub paused: bool,
+
+    // health of the refreshes, so a dead feed could be told from a quiet one
+    // when the feed was fetched and parsed without errors last time
+    pub last_success: Option<u64>,
+    pub last_error: Option<FeedError>,
+    // HTTP status of the last response, missing if the server was not reached
+    pub last_status: Option<u16>,
+    // refreshes failed in a row, the feed is retried less often the more there are
+    #[serde(default)]
+    pub failures: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedError {
+    pub kind: FeedErrorKind,
+    pub message: String,
+    // when the error happened
+    pub time: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum FeedErrorKind {
+    /// the server could not be reached, or the response could not be read
+    Fetch,
+    /// the server answered with an error status
+    Status,
+    /// the response is not a feed in any of the supported formats
+    Parse,
+    /// the feed or its entries could not be stored
+    Storage,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FeedHealth {
+    Ok,
+    // the last refreshes have failed, but the feed may recover
+    Failing,
+    // the feed has failed for so long, it is likely gone
+    Dead,
+}
+
+// failures in a row after which the feed is considered dead
+pub const DEAD_FEED_FAILURES: u32 = 10;
+
+impl StoredFeed {
+    /// the title to show, the user's one wins over the feed's own
+    pub fn display_title(&self) -> Option<&str> {
+        self.title_override.as_deref().or(self.title.as_deref())
+    }
+
+    pub fn health(&self) -> FeedHealth {
+        match self.failures {
+            0 => FeedHealth::Ok,
+            failures if failures < DEAD_FEED_FAILURES => FeedHealth::Failing,
+            _ => FeedHealth::Dead,
+        }
+    }
+
+    pub fn record_success(&mut self, now: u64, status: u16) {
+        self.last_success = Some(now);
+        self.last_status = Some(status);
+        self.failures = 0;
+    }
+
+    /// the error is kept until the next one, so it could be seen after the feed recovers
+    pub fn record_failure(&mut self, error: FeedError, status: Option<u16>) {
+        self.last_error = Some(error);
+        self.last_status = status;
+        self.failures = self.failures.saturating_add(1);
+    }
+
+    /// makes the next fetch unconditional, so the feed is parsed even if it has not changed
+    pub fn forget_validators(&mut self) {
+        self.etag = None;
+        self.last_modified = None;
+        self.body_hash = None;
+    }
+}
+
+/// a feed linked from an HTML page, offered when the page is given instead of the feed
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedCandidate {
+    pub url: String,
+    pub title: Option<String>,
+    // the type the page gives, e.g. application/atom+xml
+    pub content_type: String,
 }
 
 #[cfg(feature = "backend")]
 impl StoredFeed {
     pub fn update_from_rss_channel(&mut self, channel: Channel) -> Result<(), Error> {
         let Channel {
-            title, description, ..
+            title,
+            description,
+            pub_date,
+            last_build_date,
+            ttl,
+            skip_hours,
+            skip_days,
+            syndication_ext,
+            categories,
+            itunes_ext,
+            dublin_core_ext,
+            ..
         } = channel;
         self.title = Some(title);
         self.description = Some(description);
-        // TODO: join last_build_date + pub_date + ttl + syndication.*
-        // TODO: deal with skip_hours, skip_days
-        //self.ext_last_update = I/
+        let dc = dublin_core_ext.unwrap_or_default();
+        self.authors = unique_names(
+            dc.creators
+                .into_iter()
+                .chain(itunes_ext.and_then(|itunes| itunes.author)),
+        );
+        self.categories = unique_names(
+            categories
+                .into_iter()
+                .map(|category| category.name)
+                .chain(dc.subjects),
+        );
+        // the content changes when the feed is built, pub_date is only about the publication
+        self.ext_last_update = last_build_date
+            .or(pub_date)
+            .and_then(|date| parse_date(&date).ok())
+            .map(|date| date.timestamp_millis() as u64);
+        self.ttl = ttl.and_then(|ttl| ttl.trim().parse().ok());
+        self.skip_hours = skip_hours
+            .iter()
+            .filter_map(|hour| hour.trim().parse().ok())
+            // some feeds use 24 for midnight
+            .map(|hour: u32| hour % 24)
+            .collect();
+        self.skip_days = skip_days
+            .iter()
+            .filter_map(|day| day.trim().parse::<Weekday>().ok())
+            .map(|day| day.num_days_from_monday())
+            .collect();
+        self.update_interval = syndication_ext
+            .and_then(|sy| syndication_interval(&sy.period.to_string(), sy.frequency));
         Ok(())
     }
 
     pub fn update_from_atom_feed(&mut self, feed: AtomFeed) -> Result<(), Error> {
-        Err(anyhow!("atom feed not implemented yet"))
+        let AtomFeed {
+            title,
+            subtitle,
+            updated,
+            authors,
+            categories,
+            ..
+        } = feed;
+        self.title = Some(title.value);
+        self.authors = unique_names(authors.into_iter().map(|author| author.name));
+        self.categories = unique_names(categories.into_iter().map(|category| category.term));
+        self.description = subtitle.map(|subtitle| subtitle.value);
+        self.ext_last_update = Some(updated.timestamp_millis() as u64);
+        Ok(())
+    }
+
+    pub fn update_from_json_feed(&mut self, feed: JsonFeed) -> Result<(), Error> {
+        let JsonFeed {
+            title,
+            description,
+            authors,
+            author,
+            ..
+        } = feed;
+        self.title = Some(title);
+        self.description = description;
+        self.authors = unique_names(
+            authors
+                .into_iter()
+                .chain(author)
+                .filter_map(|author| author.name),
+        );
+        Ok(())
+    }
+
+    pub fn update_from_rdf_channel(&mut self, channel: RdfChannel) -> Result<(), Error> {
+        let RdfChannel {
+            title,
+            description,
+            dc_date,
+            sy_update_period,
+            sy_update_frequency,
+            ..
+        } = channel;
+        self.title = Some(title);
+        self.description = Some(description);
+        // a bad date is treated as a missing one, like in RSS
+        self.ext_last_update = dc_date
+            .and_then(|date| parse_date(&date).ok())
+            .map(|date| date.timestamp_millis() as u64);
+        if sy_update_period.is_some() || sy_update_frequency.is_some() {
+            // the defaults are defined by the syndication module spec
+            let frequency = sy_update_frequency
+                .and_then(|frequency| frequency.trim().parse().ok())
+                .unwrap_or(1);
+            self.update_interval =
+                syndication_interval(sy_update_period.as_deref().unwrap_or("daily"), frequency);
+        }
+        Ok(())
+    }
+
+    /// calculates the time when the feed may be fetched next time, according to the
+    /// hints given by the feed authors: ttl, sy:updatePeriod, skipHours and skipDays,
+    /// to the polling interval, and to the failures of the last refreshes;
+    /// the interval set by the user is used as it is, even if the hints ask for a longer one
+    pub fn schedule_next_fetch(&mut self, now: DateTime<Utc>) {
+        let interval = match self.poll_interval_override {
+            Some(interval) => interval,
+            None => [
+                self.ttl.map(|ttl| ttl * 60 * 1000),
+                self.update_interval,
+                self.poll_interval,
+            ]
+            .into_iter()
+            .flatten()
+            .max()
+            .unwrap_or(0),
+        };
+        let mut next = now + Duration::milliseconds(interval as i64);
+
+        // feeds which keep failing are retried less and less often
+        if self.failures > 0 {
+            let backoff = RETRY_INTERVAL
+                .saturating_mul(2u64.saturating_pow(self.failures - 1))
+                .min(MAX_RETRY_INTERVAL);
+            next = next.max(now + Duration::milliseconds(backoff as i64));
+        }
+
+        // skipHours and skipDays are in GMT by the spec,
+        // a week of skipped hours means the hints are broken, so we give up on them
+        for _ in 0..24 * 7 {
+            if !self.skip_hours.contains(&next.hour())
+                && !self
+                    .skip_days
+                    .contains(&next.weekday().num_days_from_monday())
+            {
+                break;
+            }
+            next = (next + Duration::hours(1))
+                .with_minute(0)
+                .and_then(|next| next.with_second(0))
+                .unwrap_or(next);
+        }
+        self.next_fetch = Some(next.timestamp_millis() as u64);
     }
+
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.next_fetch.map_or(true, |next_fetch| {
+            now.timestamp_millis() as u64 >= next_fetch
+        })
+    }
+}
+
+// milliseconds to wait before retrying a failed feed, doubled on every failure in a row
+#[cfg(feature = "backend")]
+const RETRY_INTERVAL: u64 = 15 * 60 * 1000;
+#[cfg(feature = "backend")]
+const MAX_RETRY_INTERVAL: u64 = 24 * 60 * 60 * 1000;
+
+/// converts syndication module's period and frequency to milliseconds between updates
+#[cfg(feature = "backend")]
+fn syndication_interval(period: &str, frequency: u32) -> Option<u64> {
+    let period: u64 = match period.trim() {
+        "hourly" => 60 * 60,
+        "daily" => 24 * 60 * 60,
+        "weekly" => 7 * 24 * 60 * 60,
+        "monthly" => 30 * 24 * 60 * 60,
+        "yearly" => 365 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(period * 1000 / frequency.max(1) as u64)
 }
 
 #[cfg(feature = "backend")]
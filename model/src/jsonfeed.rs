@@ -0,0 +1,96 @@
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+/// JSON Feed document, both versions 1.0 and 1.1 are supported,
+/// see https://www.jsonfeed.org/version/1.1/
+#[derive(Debug, Clone, Deserialize)]
+pub struct JsonFeed {
+    pub version: String,
+    pub title: String,
+    pub home_page_url: Option<String>,
+    pub feed_url: Option<String>,
+    pub description: Option<String>,
+    pub icon: Option<String>,
+    // 1.1 replaced a single author with a list
+    #[serde(default)]
+    pub authors: Vec<JsonFeedAuthor>,
+    pub author: Option<JsonFeedAuthor>,
+    // the items are read one by one with `JsonFeedItem::from_value`, so a bad one is skipped alone
+    #[serde(default)]
+    pub items: Vec<Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JsonFeedItem {
+    // the spec requires id to be a string, but some 1.0 feeds use numbers or leave it out
+    #[serde(default, deserialize_with = "id_from_any")]
+    pub id: Option<String>,
+    pub url: Option<String>,
+    pub external_url: Option<String>,
+    pub title: Option<String>,
+    pub content_html: Option<String>,
+    pub content_text: Option<String>,
+    pub summary: Option<String>,
+    pub image: Option<String>,
+    pub date_published: Option<String>,
+    pub date_modified: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<JsonFeedAuthor>,
+    pub author: Option<JsonFeedAuthor>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub attachments: Vec<JsonFeedAttachment>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JsonFeedAuthor {
+    pub name: Option<String>,
+    pub url: Option<String>,
+    pub avatar: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JsonFeedAttachment {
+    pub url: String,
+    pub mime_type: Option<String>,
+    pub title: Option<String>,
+    pub size_in_bytes: Option<u64>,
+    pub duration_in_seconds: Option<f64>,
+}
+
+impl JsonFeed {
+    pub fn read_from(data: &[u8]) -> Result<Self, Error> {
+        let feed: Self = serde_json::from_slice(data)?;
+        // version 1 is often published with the http scheme
+        let known = [
+            "https://jsonfeed.org/version/",
+            "http://jsonfeed.org/version/",
+        ]
+        .iter()
+        .any(|prefix| feed.version.starts_with(prefix));
+        if !known {
+            return Err(anyhow!("unknown json feed version {}", feed.version));
+        }
+        Ok(feed)
+    }
+}
+
+impl JsonFeedItem {
+    pub fn from_value(item: Value) -> Result<Self, Error> {
+        Ok(serde_json::from_value(item)?)
+    }
+}
+
+fn id_from_any<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(s) => Ok(Some(s)),
+        Value::Number(n) => Ok(Some(n.to_string())),
+        Value::Null => Ok(None),
+        other => Err(serde::de::Error::custom(format!(
+            "item id must be a string, got {}",
+            other
+        ))),
+    }
+}
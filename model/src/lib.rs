@@ -1,4 +1,8 @@
 pub mod entry;
 pub mod events;
 pub mod feed;
+#[cfg(feature = "backend")]
+pub mod jsonfeed;
+#[cfg(feature = "backend")]
+pub mod rdf;
 pub mod util;
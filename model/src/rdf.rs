@@ -0,0 +1,157 @@
+use std::io::BufRead;
+
+use anyhow::{anyhow, Error};
+use quick_xml::events::Event;
+use quick_xml::name::{Namespace, ResolveResult};
+use quick_xml::NsReader;
+
+const RDF_NAMESPACE: &[u8] = b"http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const RSS_NAMESPACE: &[u8] = b"http://purl.org/rss/1.0/";
+const DC_NAMESPACE: &[u8] = b"http://purl.org/dc/elements/1.1/";
+const CONTENT_NAMESPACE: &[u8] = b"http://purl.org/rss/1.0/modules/content/";
+const SYNDICATION_NAMESPACE: &[u8] = b"http://purl.org/rss/1.0/modules/syndication/";
+
+/// RSS 1.0 document, see https://web.resource.org/rss/1.0/spec
+///
+/// Unlike RSS 2.0, items are not the children of a channel, but its siblings
+/// under the rdf:RDF root, so the rss crate cannot read it
+#[derive(Debug, Clone, Default)]
+pub struct RdfChannel {
+    pub title: String,
+    pub link: String,
+    pub description: String,
+    pub dc_date: Option<String>,
+    pub sy_update_period: Option<String>,
+    pub sy_update_frequency: Option<String>,
+    pub items: Vec<RdfItem>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RdfItem {
+    // rdf:about is the unique identifier of an item
+    pub about: Option<String>,
+    pub title: Option<String>,
+    pub link: Option<String>,
+    pub description: Option<String>,
+    pub content: Option<String>,
+    pub dc_date: Option<String>,
+    pub dc_creators: Vec<String>,
+    pub dc_subjects: Vec<String>,
+}
+
+impl RdfChannel {
+    pub fn read_from<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let mut reader = NsReader::from_reader(reader);
+        reader.trim_text(true);
+
+        let mut channel = RdfChannel::default();
+        let mut item: Option<RdfItem> = None;
+        let mut in_channel = false;
+        let mut depth = 0usize;
+        let mut text = String::new();
+        let mut buf = Vec::new();
+        loop {
+            let (ns, event) = reader.read_resolved_event_into(&mut buf)?;
+            match event {
+                Event::Start(e) => {
+                    depth += 1;
+                    text.clear();
+                    let local = e.local_name();
+                    match depth {
+                        1 if !is_ns(&ns, RDF_NAMESPACE) || local.as_ref() != b"RDF" => {
+                            return Err(anyhow!("not an RDF document"));
+                        }
+                        2 if is_ns(&ns, RSS_NAMESPACE) && local.as_ref() == b"channel" => {
+                            in_channel = true;
+                        }
+                        2 if is_ns(&ns, RSS_NAMESPACE) && local.as_ref() == b"item" => {
+                            let about = e
+                                .attributes()
+                                .filter_map(|attr| attr.ok())
+                                .find(|attr| attr.key.local_name().as_ref() == b"about")
+                                .and_then(|attr| attr.unescape_value().ok())
+                                .map(|value| value.into_owned());
+                            item = Some(RdfItem {
+                                about,
+                                ..Default::default()
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Text(e) => text.push_str(&e.unescape()?),
+                Event::CData(e) => text.push_str(&String::from_utf8_lossy(&e.into_inner())),
+                Event::End(e) => {
+                    let local = e.local_name();
+                    if depth == 2 {
+                        if let Some(item) = item.take() {
+                            channel.items.push(item);
+                        }
+                        in_channel = false;
+                    } else if depth == 3 {
+                        let value = std::mem::take(&mut text);
+                        if let Some(item) = &mut item {
+                            item.set_field(&ns, local.as_ref(), value);
+                        } else if in_channel {
+                            channel.set_field(&ns, local.as_ref(), value);
+                        }
+                    }
+                    depth = depth.saturating_sub(1);
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+        }
+
+        if depth != 0 {
+            return Err(anyhow!("unexpected end of RDF document"));
+        }
+        Ok(channel)
+    }
+
+    fn set_field(&mut self, ns: &ResolveResult, name: &[u8], value: String) {
+        if is_ns(ns, RSS_NAMESPACE) {
+            match name {
+                b"title" => self.title = value,
+                b"link" => self.link = value,
+                b"description" => self.description = value,
+                _ => {}
+            }
+        } else if is_ns(ns, DC_NAMESPACE) && name == b"date" {
+            self.dc_date = Some(value);
+        } else if is_ns(ns, SYNDICATION_NAMESPACE) {
+            match name {
+                b"updatePeriod" => self.sy_update_period = Some(value),
+                b"updateFrequency" => self.sy_update_frequency = Some(value),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl RdfItem {
+    fn set_field(&mut self, ns: &ResolveResult, name: &[u8], value: String) {
+        if is_ns(ns, RSS_NAMESPACE) {
+            match name {
+                b"title" => self.title = Some(value),
+                b"link" => self.link = Some(value),
+                b"description" => self.description = Some(value),
+                _ => {}
+            }
+        } else if is_ns(ns, DC_NAMESPACE) {
+            match name {
+                b"date" => self.dc_date = Some(value),
+                b"creator" => self.dc_creators.push(value),
+                b"subject" => self.dc_subjects.push(value),
+                _ => {}
+            }
+        } else if is_ns(ns, CONTENT_NAMESPACE) && name == b"encoded" {
+            self.content = Some(value);
+        }
+    }
+}
+
+fn is_ns(ns: &ResolveResult, expected: &[u8]) -> bool {
+    matches!(ns, ResolveResult::Bound(Namespace(ns)) if *ns == expected)
+}
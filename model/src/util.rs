@@ -1,7 +1,186 @@
 #[cfg(feature = "backend")]
+use anyhow::{anyhow, Error};
+#[cfg(feature = "backend")]
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+#[cfg(feature = "backend")]
 use xxhash_rust::xxh3::xxh3_64;
 
 #[cfg(feature = "backend")]
 pub fn hash(data: &[u8]) -> u64 {
     xxh3_64(data)
 }
+
+/// month names as they are met in the wild, in different languages
+/// and with or without the abbreviation, the English abbreviation goes first
+#[cfg(feature = "backend")]
+const MONTH_NAMES: [&str; 12] = [
+    "jan january januar janv janvier ene enero gen gennaio янв января январь",
+    "feb february februar fév févr février fev febrero febbraio fevereiro фев февраля февраль",
+    "mar march mär märz mars marzo mrt maart março мар марта март",
+    "apr april avr avril abr abril aprile апр апреля апрель",
+    "may mai mayo mag maggio mei maio мая май",
+    "jun june juni juin junio giu giugno junho июн июня июнь",
+    "jul july juli juil juillet julio lug luglio julho июл июля июль",
+    "aug august août aou ago agosto augustus авг августа август",
+    "sep sept september septembre septiembre set settembre setembro сен сент сентября сентябрь",
+    "oct october okt oktober octobre octubre ott ottobre out outubro окт октября октябрь",
+    "nov november novembre noviembre novembro ноя нояб ноября ноябрь",
+    "dec december dez dezember déc décembre dic diciembre dicembre dezembro дек декабря декабрь",
+];
+
+#[cfg(feature = "backend")]
+const ENGLISH_MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// obsolete zone names from RFC 822 and a few more that feeds tend to use
+#[cfg(feature = "backend")]
+const ZONE_NAMES: [(&str, &str); 16] = [
+    ("GMT", "+0000"),
+    ("UT", "+0000"),
+    ("UTC", "+0000"),
+    ("Z", "+0000"),
+    ("EST", "-0500"),
+    ("EDT", "-0400"),
+    ("CST", "-0600"),
+    ("CDT", "-0500"),
+    ("MST", "-0700"),
+    ("MDT", "-0600"),
+    ("PST", "-0800"),
+    ("PDT", "-0700"),
+    ("CET", "+0100"),
+    ("CEST", "+0200"),
+    ("MSK", "+0300"),
+    ("JST", "+0900"),
+];
+
+/// parses dates in any of the formats met in feeds: strict RFC 2822,
+/// ISO 8601 (including the W3C subset used by dc:date), and RFC 2822 with
+/// common defects, like a missing or wrong weekday, non-English month names,
+/// named timezones, two-digit years or missing seconds
+#[cfg(feature = "backend")]
+pub fn parse_date(date: &str) -> Result<DateTime<FixedOffset>, Error> {
+    let date = date.trim();
+    if let Ok(parsed) = DateTime::parse_from_rfc2822(date) {
+        return Ok(parsed);
+    }
+    if let Ok(parsed) = parse_iso8601(date) {
+        return Ok(parsed);
+    }
+    parse_loose_rfc2822(date).ok_or_else(|| anyhow!("unknown date format: {:?}", date))
+}
+
+#[cfg(feature = "backend")]
+fn parse_iso8601(date: &str) -> Result<DateTime<FixedOffset>, Error> {
+    if let Ok(parsed) = DateTime::parse_from_rfc3339(date) {
+        return Ok(parsed);
+    }
+    for format in [
+        "%Y-%m-%dT%H:%M%:z",
+        "%Y-%m-%dT%H:%M:%S%.f%z",
+        "%Y-%m-%d %H:%M:%S%:z",
+    ] {
+        if let Ok(parsed) = DateTime::parse_from_str(date, format) {
+            return Ok(parsed);
+        }
+    }
+    // dates without a zone are considered to be UTC
+    let naive = date.strip_suffix('Z').unwrap_or(date);
+    for format in [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S",
+    ] {
+        if let Ok(parsed) = NaiveDateTime::parse_from_str(naive, format) {
+            return Ok(Utc.from_utc_datetime(&parsed).into());
+        }
+    }
+    let parsed = NaiveDate::parse_from_str(naive, "%Y-%m-%d")?.and_time(NaiveTime::MIN);
+    Ok(Utc.from_utc_datetime(&parsed).into())
+}
+
+#[cfg(feature = "backend")]
+fn parse_loose_rfc2822(date: &str) -> Option<DateTime<FixedOffset>> {
+    // the weekday is redundant, so it's easier to drop it than to deal with it
+    let date = match date.split_once(',') {
+        Some((weekday, rest)) if weekday.chars().all(|c| c.is_alphabetic() || c == '.') => rest,
+        _ => date,
+    };
+
+    let mut tokens = Vec::new();
+    for token in date.split_whitespace() {
+        let lowercase = token
+            .trim_end_matches(|c| c == '.' || c == ',')
+            .to_lowercase();
+        if let Some(month) = MONTH_NAMES
+            .iter()
+            .position(|names| names.split_whitespace().any(|name| name == lowercase))
+        {
+            tokens.push(ENGLISH_MONTHS[month].to_string());
+        } else if let Some((_, offset)) = ZONE_NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(token))
+        {
+            tokens.push(offset.to_string());
+        } else if lowercase.chars().all(|c| c.is_alphabetic()) {
+            // some weekday without a comma
+            continue;
+        } else {
+            tokens.push(token.trim_end_matches(',').to_string());
+        }
+    }
+    // the zone is optional, UTC is assumed when it is missing
+    if tokens.len() == 4 {
+        tokens.push("+0000".to_string());
+    }
+    let date = tokens.join(" ");
+
+    // two-digit years go first, %Y would take "03" for the year 3
+    for format in [
+        "%d %b %y %H:%M:%S %z",
+        "%d %b %y %H:%M %z",
+        "%d %b %Y %H:%M:%S %z",
+        "%d %b %Y %H:%M %z",
+        "%b %d %Y %H:%M:%S %z",
+    ] {
+        if let Ok(parsed) = DateTime::parse_from_str(&date, format) {
+            return Some(parsed);
+        }
+    }
+    None
+}
+
+/// trims the names, dropping empty ones and duplicates, but keeping the order
+#[cfg(feature = "backend")]
+pub fn unique_names<I: IntoIterator<Item = String>>(names: I) -> Vec<String> {
+    let mut unique: Vec<String> = Vec::new();
+    for name in names {
+        let name = name.trim();
+        if !name.is_empty() && !unique.iter().any(|u| u == name) {
+            unique.push(name.to_string());
+        }
+    }
+    unique
+}
+
+/// RSS requires the author to be an email address, usually followed by the name
+/// in parentheses, like "editor@example.org (Jane Doe)"; the name is what we want
+#[cfg(feature = "backend")]
+pub fn rss_author_name(author: String) -> String {
+    match author
+        .trim_end()
+        .strip_suffix(')')
+        .and_then(|a| a.split_once('('))
+    {
+        Some((_, name)) if !name.trim().is_empty() => name.to_string(),
+        _ => author,
+    }
+}
+
+/// parses durations as used by itunes:duration, either seconds or [HH:]MM:SS
+#[cfg(feature = "backend")]
+pub fn parse_duration(duration: &str) -> Option<u64> {
+    duration.trim().split(':').try_fold(0u64, |total, part| {
+        Some(total * 60 + part.parse::<u64>().ok()?)
+    })
+}
@@ -10,9 +10,6 @@ use crate::credentials::AwsCreds;
 use crate::util::global_scope;
 
 const AWS_REQUEST_VERSION: &'static str = "aws4_request";
-// TODO: variable region and service
-const AWS_REGION: &'static str = "eu-west-1";
-const AWS_SERVICE: &'static str = "lambda";
 
 pub async fn sign_and_send_data(body: Vec<u8>, creds: AwsCreds) -> Result<Vec<u8>, Error> {
     //let login_data = crate::aws::LoginSettings {
@@ -106,7 +103,7 @@ pub fn new_signed_request(body: &[u8], creds: &AwsCreds) -> Result<Request, Erro
 
     let scope = format!(
         "{}/{}/{}/{}",
-        date_stamp, AWS_REGION, AWS_SERVICE, AWS_REQUEST_VERSION
+        date_stamp, creds.region, creds.service, AWS_REQUEST_VERSION
     );
     let string_to_sign = "AWS4-HMAC-SHA256".to_owned() + "\n" + // Algorithm
         &time_str + "\n" + // RequestDateTime
@@ -115,8 +112,8 @@ pub fn new_signed_request(body: &[u8], creds: &AwsCreds) -> Result<Request, Erro
 
     let access_key = "AWS4".to_owned() + &creds.access_key;
     let k_date = hmac(access_key.as_bytes(), date_stamp.as_bytes())?;
-    let k_region = hmac(&k_date, AWS_REGION.as_bytes())?;
-    let k_service = hmac(&k_region, AWS_SERVICE.as_bytes())?;
+    let k_region = hmac(&k_date, creds.region.as_bytes())?;
+    let k_service = hmac(&k_region, creds.service.as_bytes())?;
     let k_signing = hmac(&k_service, AWS_REQUEST_VERSION.as_bytes())?;
     let mut ks = String::new();
     for byte in &k_signing {
@@ -14,6 +14,18 @@ const AWS_CREDS_KEY_NAME: &'static str = "aws_credentials";
 const IDB_DB_NAME: &'static str = "aws_worker";
 const IDB_STORE_NAME: &'static str = "aws";
 
+// the region and the service the lambda was deployed to, when the profile has none
+pub const DEFAULT_AWS_REGION: &'static str = "eu-west-1";
+pub const DEFAULT_AWS_SERVICE: &'static str = "lambda";
+
+fn default_region() -> String {
+    DEFAULT_AWS_REGION.to_string()
+}
+
+fn default_service() -> String {
+    DEFAULT_AWS_SERVICE.to_string()
+}
+
 // this should only be stored in memory,
 // so we intentionally do not impl Serialize
 #[derive(Debug, Clone)]
@@ -21,6 +33,9 @@ pub struct AwsCreds {
     pub lambda_host: String,
     pub key_id: String,
     pub access_key: String,
+    // the requests are signed for them
+    pub region: String,
+    pub service: String,
 }
 
 impl AwsCreds {
@@ -29,11 +44,15 @@ impl AwsCreds {
         lambda_host: String,
         key_id: String,
         access_key: String,
+        region: String,
+        service: String,
     ) -> Result<Self, Error> {
         let aws_creds = AwsCreds {
             lambda_host,
             key_id,
             access_key,
+            region,
+            service,
         };
         //aws_creds
         //.try_encrypt(key_data)
@@ -52,6 +71,8 @@ impl AwsCreds {
             lambda_host,
             key_id,
             access_key,
+            region: self.region.clone(),
+            service: self.service.clone(),
         })
     }
 }
@@ -62,6 +83,12 @@ pub struct AwsCredsEncrypted {
     lambda_host: String,
     key_id: String,
     access_key: String,
+    // these are not secret, so they are kept as is;
+    // the profiles saved before they were added get the defaults
+    #[serde(default = "default_region")]
+    region: String,
+    #[serde(default = "default_service")]
+    service: String,
 }
 
 impl AwsCredsEncrypted {
@@ -108,6 +135,8 @@ impl AwsCredsEncrypted {
             key_id,
             access_key,
             lambda_host,
+            region: self.region.clone(),
+            service: self.service.clone(),
         })
     }
 }
@@ -10,6 +10,8 @@ use gloo_worker::{HandlerId, Worker, WorkerScope};
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
 
+use model::events::{Event, HelloResponse, Response};
+
 use crate::credentials::AwsCreds;
 use crate::credentials::AwsCredsEncrypted;
 use crate::crypto::KeyData;
@@ -20,13 +22,16 @@ pub enum WorkerRequest {
     BackendRequest(Vec<u8>),
     StatusRequest,
     SetCredsEncrypted(String, Vec<u8>),
-    SetCredsPlaintext(String, String, String, String),
+    // password, lambda host, key id, access key, region and service
+    SetCredsPlaintext(String, String, String, String, String, String),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum WorkerResponse {
     BackendResponse(Option<Vec<u8>>),
     Status(WorkerStatus),
+    // the result of the handshake made after unlocking the credentials
+    Hello(Result<HelloResponse, String>),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -136,9 +141,14 @@ impl Worker for BackendWorker {
                 }
             }
             WorkerRequest::StatusRequest => Some(self.get_status()),
-            WorkerRequest::SetCredsEncrypted(p, creds) => Some(self.set_password(p, creds)),
-            WorkerRequest::SetCredsPlaintext(password, host, id, access) => {
-                if let Some(data) = self.set_plaintext(password, host, id, access) {
+            WorkerRequest::SetCredsEncrypted(p, creds) => {
+                let status = self.set_password(p, creds);
+                self.send_hello(scope, who);
+                Some(status)
+            }
+            WorkerRequest::SetCredsPlaintext(password, host, id, access, region, service) => {
+                if let Some(data) = self.set_plaintext(password, host, id, access, region, service)
+                {
                     let b64data = Base64::encode_string(&data);
                     let inscope = scope.clone();
                     wasm_bindgen_futures::spawn_local(async move {
@@ -152,6 +162,7 @@ impl Worker for BackendWorker {
                             WorkerResponse::Status(WorkerStatus::CredsEncrypted(data)),
                         );
                     });
+                    self.send_hello(scope, who);
                     None
                 } else {
                     Some(WorkerResponse::Status(WorkerStatus::NotReady))
@@ -169,6 +180,24 @@ impl Worker for BackendWorker {
 }
 
 impl BackendWorker {
+    /// makes the handshake with the backend once the credentials are unlocked,
+    /// so the UI knows about an incompatible backend before sending anything else
+    pub(crate) fn send_hello(&self, scope: &WorkerScope<Self>, who: HandlerId) {
+        let creds = if let WorkerState::Ready(_, creds) = &self.state {
+            creds.clone()
+        } else {
+            return;
+        };
+        let inscope = scope.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let hello = hello(creds).await.map_err(|e| {
+                warn!("handshake with backend failed: {}", e);
+                e.to_string()
+            });
+            inscope.respond(who, WorkerResponse::Hello(hello));
+        });
+    }
+
     pub(crate) fn get_status(&self) -> WorkerResponse {
         match &self.state {
             WorkerState::Uninitialized => WorkerResponse::Status(WorkerStatus::Ready),
@@ -200,10 +229,12 @@ impl BackendWorker {
         lambda_host: String,
         key_id: String,
         access_key: String,
+        region: String,
+        service: String,
     ) -> Option<Vec<u8>> {
         let mut kd = KeyData::new();
         kd.fill_from_password(&password);
-        match AwsCreds::create_and_save(&kd, lambda_host, key_id, access_key) {
+        match AwsCreds::create_and_save(&kd, lambda_host, key_id, access_key, region, service) {
             Ok(creds) => {
                 let encrypted = creds.try_encrypt(&kd).expect("encrypting creds");
                 let data = serde_json::to_vec(&encrypted).expect("serializing creds");
@@ -244,6 +275,15 @@ impl BackendWorker {
     //}
 }
 
+async fn hello(creds: AwsCreds) -> Result<HelloResponse, Error> {
+    let event = serde_json::to_vec(&Event::Hello(()))?;
+    let response = sign_and_send_data(event, creds).await?;
+    match serde_json::from_slice(&response)? {
+        Response::Hello(hello) => Ok(hello),
+        response => Err(anyhow!("unexpected response to hello: {:?}", response)),
+    }
+}
+
 async fn sign_and_send_data(body: Vec<u8>, creds: AwsCreds) -> Result<Vec<u8>, Error> {
     //let login_data = crate::aws::LoginSettings {
     //// we don't want to clone an encryption key